@@ -82,7 +82,7 @@ pub fn derive_request(input: TokenStream) -> TokenStream {
         #[derive(Clone)]
         #request_struct_doc_comment
         pub struct #request_struct_ident<'a> {
-            api: &'a crate::api::Api,
+            api: crate::api::ApiRef<'a>,
             params: #params_struct_ident,
         }
 
@@ -95,7 +95,7 @@ pub fn derive_request(input: TokenStream) -> TokenStream {
             }
 
             fn get_api_ref(&self) -> &crate::api::Api {
-                self.api
+                &self.api
             }
 
             fn get_params_ref(&self) -> &Self::ParamsType {
@@ -252,6 +252,16 @@ pub fn derive_request(input: TokenStream) -> TokenStream {
     }
 
     // TargetChatId impl for rate limit tracker
+    let allows_paid_broadcast = if fields.iter().any(|f| f.name == "allow_paid_broadcast") {
+        quote! {
+            fn allows_paid_broadcast(&self) -> bool {
+                self.allow_paid_broadcast
+            }
+        }
+    } else {
+        quote! {}
+    };
+
     if let Some(f) = fields.iter().find(|f| f.name == "chat_id") {
         let type_name = f._inner.ty.to_token_stream().to_string().replace(" ", "");
 
@@ -282,6 +292,8 @@ pub fn derive_request(input: TokenStream) -> TokenStream {
                 fn get_target_chat_id(&self) -> Option<crate::entities::misc::chat_id::ChatId> {
                     #body
                 }
+
+                #allows_paid_broadcast
             }
         });
     } else {
@@ -290,6 +302,8 @@ pub fn derive_request(input: TokenStream) -> TokenStream {
                 fn get_target_chat_id(&self) -> Option<crate::entities::misc::chat_id::ChatId> {
                     None
                 }
+
+                #allows_paid_broadcast
             }
         });
     }
@@ -308,7 +322,7 @@ pub fn derive_request(input: TokenStream) -> TokenStream {
             type Output = Result<#result_type, crate::errors::ConogramError>;
 
             type IntoFuture =
-                std::pin::Pin<Box<dyn std::future::Future<Output = Self::Output> + Send + Sync + 'a>>;
+                std::pin::Pin<Box<dyn std::future::Future<Output = Self::Output> + Send + 'a>>;
 
             fn into_future(self) -> Self::IntoFuture {
                 Box::pin(crate::request::RequestT::#send_ident(self))
@@ -319,7 +333,7 @@ pub fn derive_request(input: TokenStream) -> TokenStream {
             type Output = Result<#result_type, crate::errors::ConogramError>;
 
             type IntoFuture =
-                std::pin::Pin<Box<dyn std::future::Future<Output = Self::Output> + Send + Sync + 'a>>;
+                std::pin::Pin<Box<dyn std::future::Future<Output = Self::Output> + Send + 'a>>;
 
             fn into_future(self) -> Self::IntoFuture {
                 Box::pin(crate::request::RequestT::#send_ref_ident(self))
@@ -430,12 +444,12 @@ pub fn derive_request(input: TokenStream) -> TokenStream {
     stream.extend(quote! {
         impl<'a> #request_struct_ident<'a> {
             pub fn new(
-                api: &'a crate::api::Api,
+                api: impl Into<crate::api::ApiRef<'a>>,
                 #constructor_method_params
             ) -> Self {
                 #text_entities_match
                 Self {
-                    api,
+                    api: api.into(),
                     params: #params_struct_ident {
                         #constructor_invoke_params
                         #default_
@@ -443,6 +457,15 @@ pub fn derive_request(input: TokenStream) -> TokenStream {
                 }
             }
 
+            /// Hold the [Api](crate::api::Api) via [Arc](std::sync::Arc), so the request can be spawned, stored or sent to another task
+            #[must_use]
+            pub fn shared(self, api: std::sync::Arc<crate::api::Api>) -> #request_struct_ident<'static> {
+                #request_struct_ident {
+                    api: api.into(),
+                    params: self.params,
+                }
+            }
+
             #setter_impls
         }
     });
@@ -584,3 +607,186 @@ fn request_struct_ident_to_snake_case(ident: &Ident) -> Ident {
 
     format_ident!("{}", out)
 }
+
+/// Derive ``conogram::commands::BotCommands`` on an enum of bot commands
+///
+/// See ``conogram::commands::BotCommands`` for the supported ``#[command(...)]`` attributes
+#[proc_macro_derive(BotCommands, attributes(command))]
+pub fn derive_bot_commands(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match bot_commands_impl(&input) {
+        Ok(stream) => stream.into(),
+        Err(err) => err.into_compile_error().into(),
+    }
+}
+
+fn bot_commands_impl(input: &DeriveInput) -> Result<TokenStream2, Error> {
+    let Data::Enum(data) = &input.data else {
+        return Err(Error::new(input.span(), "BotCommands can only be derived on enums"));
+    };
+
+    let enum_ident = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    let mut match_arms = TokenStream2::new();
+    // (scope, [(command, description)]) in order of first appearance
+    let mut scopes: Vec<(String, Vec<(String, String)>)> = Vec::new();
+
+    for variant in &data.variants {
+        let variant_ident = &variant.ident;
+
+        let mut command = to_snake_case(&variant_ident.to_string());
+        let mut hide = false;
+        let mut descriptions = Vec::new();
+
+        for attr in variant.attrs.iter().filter(|a| a.path().is_ident("command")) {
+            let (mut scope, mut description) = (None, None);
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    command = meta.value()?.parse::<syn::LitStr>()?.value().to_ascii_lowercase();
+                } else if meta.path.is_ident("description") {
+                    description = Some(meta.value()?.parse::<syn::LitStr>()?.value());
+                } else if meta.path.is_ident("scope") {
+                    let lit = meta.value()?.parse::<syn::LitStr>()?;
+                    if scope_tokens(&lit.value()).is_none() {
+                        return Err(Error::new(
+                            lit.span(),
+                            "Unknown scope, expected one of `default`, `all_private_chats`, `all_group_chats`, `all_chat_administrators`",
+                        ));
+                    }
+                    scope = Some(lit.value());
+                } else if meta.path.is_ident("hide") {
+                    hide = true;
+                } else {
+                    return Err(Error::new(meta.path.span(), "Unknown attribute"));
+                }
+                Ok(())
+            })?;
+
+            match (scope, description) {
+                (scope, Some(description)) => {
+                    descriptions.push((scope.unwrap_or_else(|| "default".to_string()), description));
+                }
+                (Some(_), None) => {
+                    return Err(Error::new(attr.span(), "`scope` requires a `description`"));
+                }
+                (None, None) => {}
+            }
+        }
+
+        if !hide {
+            if descriptions.is_empty() {
+                return Err(Error::new(
+                    variant.span(),
+                    "Listed commands need a `description`, or add `hide`",
+                ));
+            }
+            for (scope, description) in descriptions {
+                let entry = (command.clone(), description);
+                match scopes.iter_mut().find(|(s, _)| *s == scope) {
+                    Some((_, commands)) => commands.push(entry),
+                    None => scopes.push((scope, vec![entry])),
+                }
+            }
+        }
+
+        let construct = match &variant.fields {
+            syn::Fields::Unit => quote! { Self::#variant_ident },
+            syn::Fields::Unnamed(fields) => {
+                let count = fields.unnamed.len();
+                let values = (1..=count).map(|i| {
+                    let name = format!("argument {i}");
+                    quote! { args.next(#name)? }
+                });
+                quote! {{
+                    let mut args = ::conogram::commands::CommandArgs::new(args, #count);
+                    Self::#variant_ident(#(#values),*)
+                }}
+            }
+            syn::Fields::Named(fields) => {
+                let count = fields.named.len();
+                let values = fields.named.iter().map(|f| {
+                    let ident = f.ident.as_ref().unwrap();
+                    let name = ident.to_string();
+                    quote! { #ident: args.next(#name)? }
+                });
+                quote! {{
+                    let mut args = ::conogram::commands::CommandArgs::new(args, #count);
+                    Self::#variant_ident { #(#values),* }
+                }}
+            }
+        };
+
+        match_arms.extend(quote! {
+            #command => Ok(#construct),
+        });
+    }
+
+    let scope_entries = scopes.iter().map(|(scope, commands)| {
+        let scope = scope_tokens(scope).unwrap();
+        let commands = commands.iter().map(|(command, description)| {
+            quote! {
+                ::conogram::entities::bot_command::BotCommand {
+                    command: #command.to_string(),
+                    description: #description.to_string(),
+                    ..Default::default()
+                }
+            }
+        });
+        quote! {
+            (#scope, vec![#(#commands),*])
+        }
+    });
+
+    Ok(quote! {
+        impl #impl_generics ::conogram::commands::BotCommands for #enum_ident #type_generics #where_clause {
+            fn parse_command(
+                command: &str,
+                args: &str,
+            ) -> Result<Self, ::conogram::commands::ParseCommandError> {
+                // Arguments of unit variants are ignored
+                let _ = args;
+                match command.to_ascii_lowercase().as_str() {
+                    #match_arms
+                    _ => Err(::conogram::commands::ParseCommandError::UnknownCommand(command.to_string())),
+                }
+            }
+
+            fn bot_commands() -> Vec<(
+                ::conogram::entities::bot_command_scope::BotCommandScope,
+                Vec<::conogram::entities::bot_command::BotCommand>,
+            )> {
+                vec![#(#scope_entries),*]
+            }
+        }
+    })
+}
+
+fn scope_tokens(scope: &str) -> Option<TokenStream2> {
+    let variant = match scope {
+        "default" => format_ident!("Default"),
+        "all_private_chats" => format_ident!("AllPrivateChats"),
+        "all_group_chats" => format_ident!("AllGroupChats"),
+        "all_chat_administrators" => format_ident!("AllChatAdministrators"),
+        _ => return None,
+    };
+    Some(quote! {
+        ::conogram::entities::bot_command_scope::BotCommandScope::#variant(Default::default())
+    })
+}
+
+fn to_snake_case(ident: &str) -> String {
+    let mut out = String::with_capacity(ident.len());
+    for (i, char) in ident.chars().enumerate() {
+        if char.is_ascii_uppercase() {
+            if i > 0 {
+                out.push('_');
+            }
+            out.push(char.to_ascii_lowercase());
+        } else {
+            out.push(char);
+        }
+    }
+    out
+}
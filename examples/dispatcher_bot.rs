@@ -0,0 +1,45 @@
+use conogram::{
+    api::{Api, ApiConfig},
+    dispatcher::{Dispatcher, Filter, Handler},
+    entities::update::AllowedUpdates,
+};
+
+fn main() {
+    let bot_token = "123456:AABBCCDDEEFF";
+    let api_config = ApiConfig::remote(bot_token, false);
+    let mut api = Api::new(api_config);
+    api.set_allowed_updates([AllowedUpdates::Message, AllowedUpdates::CallbackQuery]);
+
+    let rt = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(rt) => rt,
+        Err(err) => {
+            println!("Error creating tokio runtime: {err}");
+            return;
+        }
+    };
+
+    let dispatcher = Dispatcher::new(api)
+        .handler(
+            Handler::message(|api, message| async move {
+                message.reply(&api, "Hi!").await?;
+                Ok(())
+            })
+            .filter(Filter::command("start") & Filter::private()),
+        )
+        .handler(Handler::message(|api, message| async move {
+            message.copy_to(&api, message.chat.id).await?;
+            Ok(())
+        }))
+        .handler(Handler::callback_query(|api, query| async move {
+            query.answer(&api).await?;
+            Ok(())
+        }));
+
+    match rt.block_on(dispatcher.run_polling()) {
+        Ok(()) => {}
+        Err(err) => println!("Error running bot: {err}"),
+    }
+}
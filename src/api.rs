@@ -1,23 +1,35 @@
 use std::{
     any::{Any, TypeId},
+    borrow::Cow,
     fmt::Debug,
     future::IntoFuture,
-    sync::atomic::AtomicI64,
+    ops::Deref,
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicI64, Ordering},
+    },
     time::{Duration, Instant},
 };
 
 use dashmap::DashMap;
 use serde::{Serialize, de::DeserializeOwned};
+use tokio::io::AsyncWrite;
 
 use crate::{
+    cassette::{RecordingTransport, ReplayTransport},
     chat_member_cache::ChatMemberCache,
-    client::TgApiClient,
+    chat_migrations::ChatMigrations,
+    client::{MAX_DOWNLOAD_SIZE, TgApiClient},
     entities::{
         chat_member::ChatMember,
-        misc::{chat_id::ChatId, input_file::GetFiles},
+        file::File,
+        message::Message,
+        misc::{chat_id::ChatId, formatting::FormattedText, input_file::GetFiles},
+        reply_parameters::ReplyParameters,
         update::{AllowedUpdates, Update},
     },
-    errors::{ConogramError, ConogramErrorType, TgApiError},
+    errors::{ApiErrorKind, ConogramError, ConogramErrorType},
     methods::{
         edit_message_caption::EditMessageCaptionRequest, edit_message_text::EditMessageTextRequest,
         get_chat_member::GetChatMemberParams, send_animation::SendAnimationRequest,
@@ -25,8 +37,14 @@ use crate::{
         send_message::SendMessageRequest, send_photo::SendPhotoRequest, send_poll::SendPollRequest,
         send_voice::SendVoiceRequest,
     },
+    metrics::MetricsSink,
+    offset_store::{AckMode, OffsetStore},
+    rate_limiter::RateLimiter,
+    redact::{REDACTED, redact_tokens},
     request::{RequestT, TargetChatId},
+    retry::{DefaultRetryPolicy, RetryPolicy},
     server_config::ApiServerConfig,
+    transport::{ReqwestTransport, Transport},
 };
 
 macro_rules! set_default_param {
@@ -41,21 +59,49 @@ macro_rules! set_default_param {
     }
 }
 
+/// Delay between polls in [`AckMode::Manual`] while all pending updates are already being handled
+const UNACKED_UPDATES_POLL_DELAY: Duration = Duration::from_millis(500);
+
+/// Max number of updates returned by a single `getUpdates` call
+const UPDATES_BATCH_LIMIT: usize = 100;
+
 pub struct ApiToken(String);
 impl ApiToken {
     pub(crate) const fn leak(&self) -> &str {
         self.0.as_str()
     }
+
+    /// Id of the bot, the part of the token before `:`. Returns `None` if the token is malformed
+    #[must_use]
+    pub fn bot_id(&self) -> Option<i64> {
+        self.0.split_once(':')?.0.parse().ok()
+    }
+
+    /// Replace the secret part of this token, and of anything else that looks like a bot token, in `text`
+    #[must_use]
+    pub fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if self.0.is_empty() || !text.contains(&self.0) {
+            return redact_tokens(text);
+        }
+
+        let redacted = match self.0.split_once(':') {
+            Some((bot_id, _)) => format!("{bot_id}:{REDACTED}"),
+            None => REDACTED.to_owned(),
+        };
+        Cow::Owned(redact_tokens(&text.replace(&self.0, &redacted)).into_owned())
+    }
 }
 impl Debug for ApiToken {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut token_splits = self.0.split(':');
         f.debug_struct("ApiToken")
-            .field("bot_id", &token_splits.next().unwrap_or("Unknown"))
             .field(
-                "token",
-                &format!("{}...", &token_splits.next().unwrap_or("Unknown")[..6]),
+                "bot_id",
+                &self
+                    .0
+                    .split_once(':')
+                    .map_or("Unknown", |(bot_id, _)| bot_id),
             )
+            .field("token", &REDACTED)
             .finish()
     }
 }
@@ -69,6 +115,9 @@ impl<T: AsRef<str>> From<T> for ApiToken {
 pub struct ApiConfig {
     pub token: ApiToken,
     pub server_config: ApiServerConfig,
+
+    /// [ReqwestTransport] with default [reqwest::Client] by default
+    pub transport: Arc<dyn Transport>,
 }
 
 impl ApiConfig {
@@ -76,9 +125,38 @@ impl ApiConfig {
         Self {
             token: bot_token.into(),
             server_config: server_config.unwrap_or_else(|| ApiServerConfig::remote(false)),
+            transport: Arc::new(ReqwestTransport::default()),
         }
     }
 
+    /// Use preconfigured [reqwest::Client], e.g. with a proxy, timeouts or custom TLS
+    ///
+    /// Note: long polling requests last up to [polling timeout](Api::set_polling_timeout), client's timeout must be longer
+    #[must_use]
+    pub fn http_client(self, http_client: reqwest::Client) -> Self {
+        self.transport(ReqwestTransport::new(http_client))
+    }
+
+    /// Send requests using a custom [Transport], e.g. a mock one in tests
+    #[must_use]
+    pub fn transport(mut self, transport: impl Transport) -> Self {
+        self.transport = Arc::new(transport);
+        self
+    }
+
+    /// Record all requests and responses to a JSONL cassette at `path`, see [`RecordingTransport`]
+    ///
+    /// Note: wraps the current transport, so it must be called after [`ApiConfig::http_client`] or [`ApiConfig::transport`]
+    pub fn record(self, path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let transport = RecordingTransport::new(self.transport.clone(), path)?;
+        Ok(self.transport(transport))
+    }
+
+    /// Serve responses from a JSONL cassette at `path` instead of sending requests, see [`ReplayTransport`]
+    pub fn replay(self, path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(self.transport(ReplayTransport::open(path)?))
+    }
+
     pub fn remote(bot_token: impl Into<ApiToken>, use_test_env: bool) -> Self {
         Self::new(bot_token, Some(ApiServerConfig::remote(use_test_env)))
     }
@@ -103,6 +181,7 @@ impl Debug for ApiConfig {
                 &self.token.leak().split(':').next().unwrap_or("Unknown"),
             )
             .field("server_config", &self.server_config)
+            .field("transport", &self.transport)
             .finish()
     }
 }
@@ -116,9 +195,15 @@ pub struct Api {
     chat_member_cache: Option<ChatMemberCache>,
 
     flood_wait_hits: DashMap<(String, Option<ChatId>), (Instant, Duration)>,
+    rate_limiter: Option<RateLimiter>,
+    retry_policy: Arc<dyn RetryPolicy>,
 
-    allowed_updates: Vec<String>,
+    pub(crate) allowed_updates: Vec<String>,
     get_updates_offset: AtomicI64,
+    received_updates_offset: AtomicI64,
+    delivery_blocked: AtomicBool,
+    offset_store: Option<Box<dyn OffsetStore>>,
+    ack_mode: AckMode,
     polling_timeout: u64,
 }
 
@@ -127,13 +212,54 @@ impl Debug for Api {
         f.debug_struct("Api")
             .field("client", &self.client)
             .field("request_stats_enabled", &self.request_stats_enabled)
+            .field("rate_limiter", &self.rate_limiter)
+            .field("retry_policy", &self.retry_policy)
             .field("allowed_updates", &self.allowed_updates)
             .field("get_updates_offset", &self.get_updates_offset)
+            .field("offset_store", &self.offset_store)
+            .field("ack_mode", &self.ack_mode)
             .field("polling_timeout", &self.polling_timeout)
             .finish_non_exhaustive()
     }
 }
 
+/// [Api] held by a request: borrowed, or shared via [Arc] so the request is `'static`
+/// and can be spawned, stored in a queue or sent to another task
+#[derive(Debug, Clone)]
+pub enum ApiRef<'a> {
+    Borrowed(&'a Api),
+    Shared(Arc<Api>),
+}
+
+impl Deref for ApiRef<'_> {
+    type Target = Api;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Borrowed(api) => api,
+            Self::Shared(api) => api,
+        }
+    }
+}
+
+impl<'a> From<&'a Api> for ApiRef<'a> {
+    fn from(api: &'a Api) -> Self {
+        Self::Borrowed(api)
+    }
+}
+
+impl<'a> From<&'a Arc<Api>> for ApiRef<'a> {
+    fn from(api: &'a Arc<Api>) -> Self {
+        Self::Borrowed(api)
+    }
+}
+
+impl From<Arc<Api>> for ApiRef<'static> {
+    fn from(api: Arc<Api>) -> Self {
+        Self::Shared(api)
+    }
+}
+
 impl Api {
     #[must_use]
     pub fn new(config: ApiConfig) -> Self {
@@ -142,6 +268,10 @@ impl Api {
 
             allowed_updates: vec![],
             get_updates_offset: AtomicI64::new(0),
+            received_updates_offset: AtomicI64::new(0),
+            delivery_blocked: AtomicBool::new(false),
+            offset_store: None,
+            ack_mode: AckMode::default(),
             polling_timeout: 600,
 
             chat_member_cache: None,
@@ -149,9 +279,18 @@ impl Api {
             request_stats_enabled: false,
 
             flood_wait_hits: DashMap::new(),
+            rate_limiter: None,
+            retry_policy: Arc::new(DefaultRetryPolicy::default()),
         }
     }
 
+    /// Replace the bot token, e.g. after [replaceManagedBotToken](https://core.telegram.org/bots/api/#replacemanagedbottoken)
+    ///
+    /// Note: requests which are already sent (including a pending long poll) still use the previous token
+    pub fn set_token(&self, token: impl Into<ApiToken>) {
+        self.client.set_token(&token.into());
+    }
+
     /// Enable [ChatMember] caching from updates and requests
     ///
     /// Notes:
@@ -182,6 +321,7 @@ impl Api {
     ///
     /// Note:
     /// * [Api::set_request_stats_enabled()] must be called to enable stats collection
+    /// * See [`Api::set_metrics_sink`] for latency, errors and other metrics
     pub fn get_request_stats(&self) -> DashMap<String, usize> {
         if !self.request_stats_enabled {
             log::warn!("Called get_request_stats() with disabled statistics collection");
@@ -189,20 +329,66 @@ impl Api {
         self.request_stats.clone()
     }
 
-    pub(crate) fn register_flood_wait_hit<Request: RequestT>(
+    /// Delay message sending requests before they hit Telegram limits, see [RateLimiter]
+    ///
+    /// Notes:
+    /// * Disabled by default
+    /// * Pass `Some(RateLimiter::default())` to use documented limits
+    pub fn set_rate_limiter(&mut self, rate_limiter: Option<RateLimiter>) {
+        self.rate_limiter = rate_limiter;
+    }
+
+    /// Decide which errors are retried by [`RequestT::wrap`] and how, [DefaultRetryPolicy] by default
+    pub fn set_retry_policy(&mut self, retry_policy: impl RetryPolicy + 'static) {
+        self.retry_policy = Arc::new(retry_policy);
+    }
+
+    #[must_use]
+    pub fn retry_policy(&self) -> &dyn RetryPolicy {
+        self.retry_policy.as_ref()
+    }
+
+    pub(crate) async fn wait_rate_limit<Request: RequestT>(&self, params: &Request::ParamsType) {
+        self.wait_method_rate_limit(
+            Request::get_name(),
+            params.get_target_chat_id().as_ref(),
+            params.allows_paid_broadcast(),
+        )
+        .await;
+    }
+
+    pub(crate) async fn wait_method_rate_limit(
         &self,
-        request: &Request,
+        method: &str,
+        chat_id: Option<&ChatId>,
+        paid_broadcast: bool,
+    ) {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire(method, chat_id, paid_broadcast).await;
+        }
+    }
+
+    pub(crate) fn register_flood_wait_hit(
+        &self,
+        method: &str,
+        chat_id: Option<&ChatId>,
         retry_after: u64,
     ) {
-        let request_name = Request::get_name();
-        let target_chat_id = if request_name.contains("message") {
-            request.get_params_ref().get_target_chat_id()
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.register_flood_wait(method, chat_id, Duration::from_secs(retry_after));
+        }
+        if let Some(metrics_sink) = self.metrics_sink() {
+            metrics_sink.record_flood_wait(method, chat_id, Duration::from_secs(retry_after));
+        }
+
+        let target_chat_id = if method.contains("message") {
+            chat_id.cloned()
         } else {
             None
         };
 
         self.flood_wait_hits.insert(
-            (request_name.into(), target_chat_id),
+            (method.into(), target_chat_id),
             (Instant::now(), Duration::from_secs(retry_after)),
         );
     }
@@ -243,6 +429,75 @@ impl Api {
         }
     }
 
+    /// Track groups upgraded to supergroups and send requests to their new ids, see [ChatMigrations]
+    ///
+    /// Note: disabled by default
+    pub fn set_chat_migrations(&mut self, chat_migrations: Option<ChatMigrations>) {
+        self.client.set_chat_migrations(chat_migrations);
+    }
+
+    #[must_use]
+    pub const fn chat_migrations(&self) -> Option<&ChatMigrations> {
+        self.client.chat_migrations()
+    }
+
+    /// Record request latency, errors, retries, flood waits and uploads, e.g. with [PrometheusMetrics](crate::metrics::PrometheusMetrics)
+    ///
+    /// Note: disabled by default
+    pub fn set_metrics_sink(&mut self, metrics_sink: Option<Arc<dyn MetricsSink>>) {
+        self.client.set_metrics_sink(metrics_sink);
+    }
+
+    #[must_use]
+    pub fn metrics_sink(&self) -> Option<&dyn MetricsSink> {
+        self.client.metrics_sink()
+    }
+
+    /// Persist updates offset in the `offset_store`, polling continues from the stored offset if there is one
+    ///
+    /// Note: the store is written every time the offset moves, see [`Api::set_ack_mode`]
+    pub fn set_offset_store(
+        &mut self,
+        offset_store: impl OffsetStore + 'static,
+    ) -> Result<(), std::io::Error> {
+        if let Some(offset) = offset_store.load()? {
+            self.get_updates_offset.store(offset, Ordering::Relaxed);
+        }
+        self.offset_store = Some(Box::new(offset_store));
+        Ok(())
+    }
+
+    /// Choose when received updates are confirmed, [`AckMode::OnReceive`] by default
+    pub const fn set_ack_mode(&mut self, ack_mode: AckMode) {
+        self.ack_mode = ack_mode;
+    }
+
+    #[must_use]
+    pub const fn ack_mode(&self) -> AckMode {
+        self.ack_mode
+    }
+
+    /// Confirm that all updates up to and including `update_id` were handled
+    ///
+    /// Notes:
+    /// * Does nothing in [`AckMode::OnReceive`], as updates are confirmed when received
+    /// * Offset never moves back, acknowledging older updates is a no-op
+    pub fn ack_update(&self, update_id: i64) {
+        if self.ack_mode == AckMode::Manual {
+            self.advance_updates_offset(update_id + 1);
+        }
+    }
+
+    fn advance_updates_offset(&self, offset: i64) {
+        let prev_offset = self.get_updates_offset.fetch_max(offset, Ordering::Relaxed);
+        if prev_offset < offset
+            && let Some(offset_store) = &self.offset_store
+            && let Err(err) = offset_store.store(offset)
+        {
+            log::warn!("Failed to store updates offset {offset}: {err}");
+        }
+    }
+
     /// Sets the timeout for [GetUpdatesRequest] request
     pub const fn set_polling_timeout(&mut self, timeout_secs: u64) {
         self.polling_timeout = timeout_secs;
@@ -317,6 +572,14 @@ impl Api {
         self.check_allowed_updates();
     }
 
+    /// Whether deletion failed because the message is already gone or can't be deleted by the bot
+    fn is_undeletable(err: &ConogramError) -> bool {
+        matches!(
+            err.api_error_kind(),
+            Some(ApiErrorKind::MessageToDeleteNotFound | ApiErrorKind::MessageCantBeDeleted)
+        )
+    }
+
     /// Internal conogram method. Returns ``Ok(false)`` instead of `Err` if the message can't be deleted
     pub async fn delete_ephemeral_message_exp(
         &self,
@@ -329,7 +592,7 @@ impl Api {
             .wrap()
             .await;
         if let Err(err) = &result
-            && let ConogramErrorType::ApiError(_) = &err.type_
+            && Self::is_undeletable(err)
         {
             return Ok(false);
         }
@@ -344,7 +607,7 @@ impl Api {
     ) -> Result<bool, ConogramError> {
         let result = self.delete_message(chat_id, message_id).wrap().await;
         if let Err(err) = &result
-            && let ConogramErrorType::ApiError(_) = &err.type_
+            && Self::is_undeletable(err)
         {
             return Ok(false);
         }
@@ -359,13 +622,35 @@ impl Api {
     ) -> Result<bool, ConogramError> {
         let result = self.delete_messages(chat_id, message_ids).wrap().await;
         if let Err(err) = &result
-            && let ConogramErrorType::ApiError(_) = &err.type_
+            && Self::is_undeletable(err)
         {
             return Ok(false);
         }
         result
     }
 
+    /// Send a text longer than [`MAX_MESSAGE_LEN`](crate::entities::misc::split::MAX_MESSAGE_LEN) as several messages, each one replying to the previous
+    ///
+    /// Messages are sent with [`RequestT::wrap`], sending stops at the first error
+    pub async fn send_message_split(
+        &self,
+        chat_id: impl Into<ChatId> + Send,
+        text: impl Into<FormattedText> + Send,
+    ) -> Result<Vec<Message>, ConogramError> {
+        let chat_id = chat_id.into();
+        let mut sent: Vec<Message> = Vec::new();
+
+        for part in text.into().split_message() {
+            let mut request = self.send_message(chat_id.clone(), part);
+            if let Some(previous) = sent.last() {
+                request = request.reply_parameters(ReplyParameters::reply(previous));
+            }
+            sent.push(Box::pin(request.wrap()).await?);
+        }
+
+        Ok(sent)
+    }
+
     /// Internal method used for caching
     pub fn preprocess_updates(&self, updates: &[Update]) {
         let mut max_update_id = None;
@@ -379,19 +664,30 @@ impl Api {
             {
                 cache.cache_update(chat_member_updated);
             }
+
+            if let Some(message) = &update.message
+                && let Some(chat_migrations) = self.chat_migrations()
+            {
+                chat_migrations.cache_message(message);
+            }
         }
 
         if let Some(max_update_id) = max_update_id {
-            self.get_updates_offset
-                .store(max_update_id + 1, std::sync::atomic::Ordering::Relaxed);
+            match self.ack_mode {
+                AckMode::OnReceive => self.advance_updates_offset(max_update_id + 1),
+                AckMode::Manual => {
+                    self.received_updates_offset
+                        .fetch_max(max_update_id + 1, Ordering::Relaxed);
+                }
+            }
         }
     }
 
     /// Poll the server for pending updates
+    ///
+    /// Note: in [`AckMode::Manual`] updates which were already returned, but not acknowledged yet, are skipped
     pub async fn poll_once(&self) -> Result<Vec<Update>, ConogramError> {
-        let offset = self
-            .get_updates_offset
-            .load(std::sync::atomic::Ordering::Relaxed);
+        let offset = self.get_updates_offset.load(Ordering::Relaxed);
 
         let r = self
             .get_updates()
@@ -399,11 +695,94 @@ impl Api {
             .offset(offset)
             .timeout(self.polling_timeout.clamp(0, i64::MAX as u64) as i64);
 
-        let updates = r.await?;
+        let mut updates = r.await?;
+
+        if self.ack_mode == AckMode::Manual {
+            let received_offset = self.received_updates_offset.load(Ordering::Relaxed);
+            let received_count = updates.len();
+            updates.retain(|update| update.update_id >= received_offset);
+
+            // Server returns unacknowledged updates immediately, don't spin while they are being handled
+            if received_count > 0 && updates.is_empty() {
+                // Newer updates are not returned until the oldest in-flight one is acknowledged
+                if received_count >= UPDATES_BATCH_LIMIT
+                    && !self.delivery_blocked.swap(true, Ordering::Relaxed)
+                {
+                    log::warn!(
+                        "Update delivery is blocked: {received_count} updates are in flight, waiting for update {offset} to be acknowledged"
+                    );
+                }
+                tokio::time::sleep(UNACKED_UPDATES_POLL_DELAY).await;
+            } else {
+                self.delivery_blocked.store(false, Ordering::Relaxed);
+            }
+        }
+
         self.preprocess_updates(&updates);
         Ok(updates)
     }
 
+    /// Confirm all updates received so far on the server side, so they are not returned by [GetUpdatesRequest](crate::methods::get_updates::GetUpdatesRequest) again
+    ///
+    /// Note: the update following the last confirmed one may be received (but not confirmed) by this call, it will be returned by the next poll
+    pub async fn commit_updates_offset(&self) -> Result<(), ConogramError> {
+        let offset = self.get_updates_offset.load(Ordering::Relaxed);
+
+        self.get_updates()
+            .allowed_updates(self.allowed_updates.clone())
+            .offset(offset)
+            .limit(1)
+            .timeout(0)
+            .await?;
+        Ok(())
+    }
+
+    /// Download the file into `writer`, returns the number of downloaded bytes
+    ///
+    /// Notes:
+    /// * [getFile](crate::methods::get_file::GetFileRequest) is called first if `file_path` is not set
+    /// * Files larger than [`MAX_DOWNLOAD_SIZE`] can only be downloaded from a local server
+    /// * Absolute paths returned by a local server in `--local` mode are read from the filesystem
+    pub async fn download_file(
+        &self,
+        file: &File,
+        writer: &mut (impl AsyncWrite + Unpin + Send),
+    ) -> Result<u64, ConogramError> {
+        let file = match &file.file_path {
+            Some(_) => Cow::Borrowed(file),
+            None => Cow::Owned(self.get_file(file.file_id.clone()).wrap().await?),
+        };
+        let Some(file_path) = &file.file_path else {
+            return Err(ConogramError::new(
+                "downloadFile",
+                &*file,
+                ConogramErrorType::IO(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    "getFile returned no file_path",
+                )),
+            ));
+        };
+
+        if !self.client.server_config().is_local()
+            && let Some(size) = file.file_size
+            && size as u64 > MAX_DOWNLOAD_SIZE
+        {
+            return Err(ConogramError::new(
+                "downloadFile",
+                &*file,
+                ConogramErrorType::FileTooBig {
+                    size: size as u64,
+                    max_size: MAX_DOWNLOAD_SIZE,
+                },
+            ));
+        }
+
+        self.client
+            .download_file(file_path, writer)
+            .await
+            .map_err(|err| ConogramError::new("downloadFile", &*file, err))
+    }
+
     /// Internal method used for API calls
     pub async fn method_json<
         ReturnType: DeserializeOwned + std::fmt::Debug + Clone + Any,
@@ -430,6 +809,9 @@ impl Api {
                         .and_modify(|n| *n += 1)
                         .or_insert(1);
                 }
+                if let Some(metrics_sink) = self.metrics_sink() {
+                    metrics_sink.record_cache_hit(method);
+                }
                 return Ok(return_type);
             }
 
@@ -485,7 +867,8 @@ impl Api {
         Self::request_ref::<Request>(&request).await
     }
 
-    /// This will make API request and automativally handle some common errors like `RetryAfter`, `BadGateway` and `GatewayTimeout`
+    /// This will make API request and automatically handle some common errors like `RetryAfter`, `BadGateway` and `GatewayTimeout`,
+    /// the same way [`RequestT::wrap`] does
     pub async fn request_ref<Request: RequestT + Sync>(
         request: &Request,
     ) -> Result<Request::ReturnType, ConogramError>
@@ -493,42 +876,6 @@ impl Api {
         for<'a> &'a Request: IntoFuture<Output = Result<Request::ReturnType, ConogramError>>,
         for<'a> <&'a Request as IntoFuture>::IntoFuture: Send,
     {
-        let mut result = request.await;
-
-        let mut wait_for = 1;
-
-        while !match &result {
-            Err(err) => {
-                if let ConogramErrorType::ApiError(error) = &err.type_ {
-                    match error {
-                        TgApiError::RetryAfter(params) => {
-                            if let Some(params) = params.parameters.as_ref() {
-                                tokio::time::sleep(Duration::from_secs(
-                                    params.retry_after.unwrap_or_default() as u64,
-                                ))
-                                .await;
-                                result = request.await;
-                                false
-                            } else {
-                                true
-                            }
-                        }
-                        TgApiError::BadGateway(_) | TgApiError::GatewayTimeout(_) => {
-                            wait_for = std::cmp::min(wait_for * 2, 60);
-                            tokio::time::sleep(Duration::from_secs(wait_for)).await;
-                            result = request.await;
-                            false
-                        }
-                        _ => true,
-                    }
-                } else {
-                    true
-                }
-            }
-
-            _ => true,
-        } {}
-
-        result
+        request.wrap().await
     }
 }
@@ -0,0 +1,220 @@
+//! Running many requests with bounded concurrency
+//!
+//! ```rust, ignore
+//! let results = Batch::new(user_ids.iter().map(|&user_id| api.ban_chat_member(chat_id, user_id)))
+//!     .concurrency(5)
+//!     .stop_on_fatal()
+//!     .run()
+//!     .await;
+//!
+//! for (i, err) in results.errors() {
+//!     log::warn!("Failed to ban {}: {err}", user_ids[i]);
+//! }
+//! ```
+
+use std::{
+    future::{Future, IntoFuture},
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use futures::{StreamExt, stream};
+
+use crate::{
+    errors::{ConogramError, ConogramErrorType, TgApiError},
+    request::RequestT,
+};
+
+/// Future of a derived request, as returned by `IntoFuture` for `&Request`
+type RequestFuture<'a, R> = Pin<
+    Box<dyn Future<Output = Result<<R as RequestT>::ReturnType, ConogramError>> + Send + Sync + 'a>,
+>;
+
+type StopPredicate<'a> = dyn Fn(&ConogramError) -> bool + Send + Sync + 'a;
+
+/// Whether the error means no further request can succeed, i.e. the bot token is revoked
+#[must_use]
+pub const fn is_fatal(err: &ConogramError) -> bool {
+    matches!(
+        err.type_,
+        ConogramErrorType::ApiError(TgApiError::Unauthorized(_))
+    )
+}
+
+/// Results of a [Batch], in order of requests
+#[derive(Debug)]
+pub struct BatchResults<T> {
+    /// `None` for requests which were not sent because the batch was stopped
+    pub results: Vec<Option<Result<T, ConogramError>>>,
+
+    /// Error which stopped the batch, its index in `results`
+    pub stopped_at: Option<usize>,
+}
+
+impl<T> BatchResults<T> {
+    /// Errors with indices of the failed requests
+    pub fn errors(&self) -> impl Iterator<Item = (usize, &ConogramError)> {
+        self.results
+            .iter()
+            .enumerate()
+            .filter_map(|(i, result)| match result {
+                Some(Err(err)) => Some((i, err)),
+                _ => None,
+            })
+    }
+
+    /// Successful results with indices of their requests
+    pub fn successes(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.results
+            .iter()
+            .enumerate()
+            .filter_map(|(i, result)| match result {
+                Some(Ok(value)) => Some((i, value)),
+                _ => None,
+            })
+    }
+
+    #[must_use]
+    pub fn is_ok(&self) -> bool {
+        self.results
+            .iter()
+            .all(|result| matches!(result, Some(Ok(_))))
+    }
+}
+
+/// Runs requests with a concurrency limit
+///
+/// Notes:
+/// * Requests are sent with [`RequestT::wrap`], so flood waits and gateway errors are retried
+/// * A request is not started while there is an ongoing flood wait for it, see [`Api::get_flood_wait_duration`](crate::api::Api::get_flood_wait_duration)
+/// * When the batch is stopped, requests in flight are finished, but no new ones are started
+pub struct Batch<'a, I> {
+    requests: I,
+    concurrency: usize,
+    stop_on: Option<Box<StopPredicate<'a>>>,
+}
+
+impl<I> std::fmt::Debug for Batch<'_, I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Batch")
+            .field("concurrency", &self.concurrency)
+            .field("stop_on", &self.stop_on.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a, I: IntoIterator> Batch<'a, I> {
+    pub fn new(requests: I) -> Self {
+        Self {
+            requests,
+            concurrency: 1,
+            stop_on: None,
+        }
+    }
+
+    /// Number of requests sent at the same time, 1 by default
+    #[must_use]
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Stop on errors after which no request can succeed, see [`is_fatal`]
+    #[must_use]
+    pub fn stop_on_fatal(self) -> Self {
+        self.stop_on(is_fatal)
+    }
+
+    /// Stop on errors matching `predicate`
+    #[must_use]
+    pub fn stop_on(
+        mut self,
+        predicate: impl Fn(&ConogramError) -> bool + Send + Sync + 'a,
+    ) -> Self {
+        self.stop_on = Some(Box::new(predicate));
+        self
+    }
+
+    /// Run all requests, or until the batch is stopped
+    ///
+    /// The future is boxed, so it can be spawned regardless of the requests' lifetimes
+    pub fn run<R>(self) -> Pin<Box<dyn Future<Output = BatchResults<R::ReturnType>> + Send + 'a>>
+    where
+        I: IntoIterator<Item = R> + Send + 'a,
+        I::IntoIter: Send,
+        R: RequestT + 'a,
+        for<'b> &'b R: IntoFuture<
+                Output = Result<R::ReturnType, ConogramError>,
+                IntoFuture = RequestFuture<'b, R>,
+            >,
+    {
+        Box::pin(self.run_inner())
+    }
+
+    async fn run_inner<R>(self) -> BatchResults<R::ReturnType>
+    where
+        I: IntoIterator<Item = R> + Send,
+        I::IntoIter: Send,
+        R: RequestT,
+        for<'b> &'b R: IntoFuture<
+                Output = Result<R::ReturnType, ConogramError>,
+                IntoFuture = RequestFuture<'b, R>,
+            >,
+    {
+        let stopped = AtomicBool::new(false);
+        let stopped = &stopped;
+        let stop_on = self.stop_on.as_deref();
+
+        let results = stream::iter(self.requests)
+            .map(|request| run_one(request, stopped, stop_on))
+            .buffered(self.concurrency);
+        let mut results = std::pin::pin!(results);
+
+        let mut batch_results = BatchResults {
+            results: Vec::new(),
+            stopped_at: None,
+        };
+        while let Some((result, stops)) = results.next().await {
+            if stops && batch_results.stopped_at.is_none() {
+                batch_results.stopped_at = Some(batch_results.results.len());
+            }
+            batch_results.results.push(result);
+        }
+
+        batch_results
+    }
+}
+
+async fn run_one<R>(
+    request: R,
+    stopped: &AtomicBool,
+    stop_on: Option<&StopPredicate<'_>>,
+) -> (Option<Result<R::ReturnType, ConogramError>>, bool)
+where
+    R: RequestT,
+    for<'b> &'b R: IntoFuture<Output = Result<R::ReturnType, ConogramError>, IntoFuture = RequestFuture<'b, R>>,
+{
+    if stopped.load(Ordering::Relaxed) {
+        return (None, false);
+    }
+
+    if let Some(wait_for) = request.get_api_ref().get_flood_wait_duration(&request) {
+        log::debug!(
+            "Delaying {} in batch for {wait_for:?} due to flood wait",
+            R::get_name()
+        );
+        tokio::time::sleep(wait_for).await;
+    }
+
+    let result = request.wrap().await;
+    let stops = result
+        .as_ref()
+        .err()
+        .zip(stop_on)
+        .is_some_and(|(err, stop_on)| stop_on(err));
+    if stops {
+        stopped.store(true, Ordering::Relaxed);
+    }
+
+    (Some(result), stops)
+}
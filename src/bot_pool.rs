@@ -0,0 +1,661 @@
+//! Many bots sharing one HTTP connection pool and one stream of updates
+//!
+//! ```rust, ignore
+//! // Bots created via the manager are added to the pool as soon as `managed_bot` updates arrive
+//! let pool = BotPool::new(BotPoolConfig::new(None).add_managed_bots(true));
+//! let manager = pool.add_bot(manager_token).await?;
+//! for token in tokens {
+//!     pool.add_bot(token).await?;
+//! }
+//!
+//! let mut updates = pool.start_polling(&CancellationToken::new());
+//! while let Some(update) = updates.recv().await {
+//!     let BotUpdate { bot_id, api, update } = update?;
+//!     // Handle the update with `api` of the bot it came from
+//! }
+//!
+//! // Revokes the token of a managed bot, its Api switches to the new one
+//! let token = pool.replace_managed_bot_token(&manager, bot_id).await?;
+//! ```
+
+use std::{
+    fmt::Debug,
+    pin::{Pin, pin},
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use dashmap::DashMap;
+use futures::{Stream, StreamExt};
+use thiserror::Error;
+use tokio::sync::mpsc;
+
+use crate::{
+    api::{Api, ApiConfig, ApiToken},
+    entities::{managed_bot_updated::ManagedBotUpdated, update::Update},
+    errors::{ConogramError, ConogramErrorType, TgApiError},
+    polling::CancellationToken,
+    request::RequestT,
+    server_config::ApiServerConfig,
+    transport::{ReqwestTransport, Transport},
+};
+
+/// How many received updates can wait to be consumed before polling is paused
+const MAX_PENDING_UPDATES: usize = 100;
+
+/// Token of a bot may be revoked just before the pool learns the new one, so `Unauthorized` polling errors are retried for this long
+const TOKEN_REPLACE_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Error)]
+pub enum BotPoolError {
+    /// Token is not in the `<bot_id>:<secret>` format
+    #[error("Invalid bot token")]
+    InvalidToken,
+
+    /// Request made for the bot failed, or its polling was stopped by a terminal error
+    #[error("Bot {bot_id}: {error}")]
+    ApiError {
+        bot_id: i64,
+        #[source]
+        error: ConogramError,
+    },
+
+    /// Webhook server IO errors
+    #[error("{0}")]
+    IO(#[from] std::io::Error),
+}
+
+/// Update tagged with the bot it was received by
+#[derive(Debug, Clone)]
+pub struct BotUpdate {
+    pub bot_id: i64,
+    pub api: Arc<Api>,
+    pub update: Update,
+}
+
+/// Setup of every [Api] created by a [BotPool], see [`BotPoolConfig::setup`]
+pub type ApiSetup = dyn Fn(&mut Api) + Send + Sync;
+
+pub struct BotPoolConfig {
+    pub server_config: ApiServerConfig,
+
+    /// Shared by all bots of the pool, [ReqwestTransport] with default [reqwest::Client] by default
+    pub transport: Arc<dyn Transport>,
+
+    /// Called for every [Api] created by the pool, e.g. to set allowed updates or a rate limiter
+    pub setup: Option<Arc<ApiSetup>>,
+
+    /// Add bots created via bots of the pool when their `managed_bot` updates arrive, `false` by default
+    ///
+    /// Note: tokens of managed bots which are already in the pool are always kept up to date
+    pub add_managed_bots: bool,
+}
+
+impl BotPoolConfig {
+    #[must_use]
+    pub fn new(server_config: Option<ApiServerConfig>) -> Self {
+        Self {
+            server_config: server_config.unwrap_or_default(),
+            transport: Arc::new(ReqwestTransport::default()),
+            setup: None,
+            add_managed_bots: false,
+        }
+    }
+
+    /// Use preconfigured [reqwest::Client], see [`ApiConfig::http_client`]
+    #[must_use]
+    pub fn http_client(self, http_client: reqwest::Client) -> Self {
+        self.transport(ReqwestTransport::new(http_client))
+    }
+
+    /// Send requests of all bots using a custom [Transport]
+    #[must_use]
+    pub fn transport(mut self, transport: impl Transport) -> Self {
+        self.transport = Arc::new(transport);
+        self
+    }
+
+    #[must_use]
+    pub fn setup(mut self, setup: impl Fn(&mut Api) + Send + Sync + 'static) -> Self {
+        self.setup = Some(Arc::new(setup));
+        self
+    }
+
+    #[must_use]
+    pub const fn add_managed_bots(mut self, add_managed_bots: bool) -> Self {
+        self.add_managed_bots = add_managed_bots;
+        self
+    }
+}
+
+impl Default for BotPoolConfig {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl Debug for BotPoolConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BotPoolConfig")
+            .field("server_config", &self.server_config)
+            .field("transport", &self.transport)
+            .field("add_managed_bots", &self.add_managed_bots)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+struct PoolBot {
+    api: Arc<Api>,
+    token: Mutex<ApiToken>,
+
+    /// Incremented every time the token is replaced
+    generation: AtomicU64,
+
+    /// Cancels polling of this bot only
+    polling: Mutex<Option<CancellationToken>>,
+}
+
+impl PoolBot {
+    fn stop_polling(&self) {
+        let cancellation_token = self
+            .polling
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(cancellation_token) = cancellation_token {
+            cancellation_token.cancel();
+        }
+    }
+}
+
+/// Where updates of the pool go to
+#[derive(Debug, Clone)]
+struct Delivery {
+    sender: mpsc::Sender<Result<BotUpdate, BotPoolError>>,
+    cancellation_token: CancellationToken,
+
+    /// Webhook base URL and secret token, bots are polled if not set
+    webhook: Option<(String, Option<String>)>,
+}
+
+#[derive(Debug)]
+struct BotPoolInner {
+    config: BotPoolConfig,
+    bots: DashMap<i64, Arc<PoolBot>>,
+    delivery: Mutex<Option<Delivery>>,
+}
+
+/// Many bots which share one [Transport] (and so one HTTP connection pool) and deliver updates to one [`BotPoolUpdates`] stream
+///
+/// Cheap to clone
+#[derive(Debug, Clone)]
+pub struct BotPool {
+    inner: Arc<BotPoolInner>,
+}
+
+impl BotPool {
+    #[must_use]
+    pub fn new(config: BotPoolConfig) -> Self {
+        Self {
+            inner: Arc::new(BotPoolInner {
+                config,
+                bots: DashMap::new(),
+                delivery: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Add a bot to the pool, it's polled or gets its webhook set right away if updates are being received
+    ///
+    /// If the bot is already in the pool, its token is replaced and the existing [Api] is returned
+    pub async fn add_bot(&self, token: impl Into<ApiToken>) -> Result<Arc<Api>, BotPoolError> {
+        let token = token.into();
+        let bot_id = token.bot_id().ok_or(BotPoolError::InvalidToken)?;
+
+        if let Some(bot) = self.bot(bot_id) {
+            self.replace_token(bot_id, &bot, token).await?;
+            return Ok(bot.api.clone());
+        }
+
+        let mut api = Api::new(ApiConfig {
+            token: token.leak().into(),
+            server_config: self.inner.config.server_config.clone(),
+            transport: self.inner.config.transport.clone(),
+        });
+        if let Some(setup) = &self.inner.config.setup {
+            setup(&mut api);
+        }
+
+        let bot = Arc::new(PoolBot {
+            api: Arc::new(api),
+            token: Mutex::new(token),
+            generation: AtomicU64::new(0),
+            polling: Mutex::new(None),
+        });
+        self.inner.bots.insert(bot_id, bot.clone());
+
+        if let Some(delivery) = self.delivery() {
+            self.deliver(bot_id, &bot, &delivery).await?;
+        }
+
+        Ok(bot.api.clone())
+    }
+
+    /// Remove the bot from the pool and stop polling it
+    ///
+    /// Note: the webhook of the bot is left as is, call [`Api::delete_webhook`] to remove it
+    #[must_use]
+    pub fn remove_bot(&self, bot_id: i64) -> Option<Arc<Api>> {
+        let (_, bot) = self.inner.bots.remove(&bot_id)?;
+        bot.stop_polling();
+        Some(bot.api.clone())
+    }
+
+    #[must_use]
+    pub fn get(&self, bot_id: i64) -> Option<Arc<Api>> {
+        self.bot(bot_id).map(|bot| bot.api.clone())
+    }
+
+    #[must_use]
+    pub fn bot_ids(&self) -> Vec<i64> {
+        self.inner.bots.iter().map(|bot| *bot.key()).collect()
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.inner.bots.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.inner.bots.is_empty()
+    }
+
+    /// Switch the bot to a new token without restarting it. Returns `false` if the bot is not in the pool
+    ///
+    /// Polling continues with the new token, the webhook is set again if updates are received via webhook
+    pub async fn set_token(
+        &self,
+        bot_id: i64,
+        token: impl Into<ApiToken>,
+    ) -> Result<bool, BotPoolError> {
+        let token = token.into();
+        if token.bot_id() != Some(bot_id) {
+            return Err(BotPoolError::InvalidToken);
+        }
+
+        let Some(bot) = self.bot(bot_id) else {
+            return Ok(false);
+        };
+        self.replace_token(bot_id, &bot, token).await?;
+        Ok(true)
+    }
+
+    /// Revoke the token of a bot managed by `manager` with [replaceManagedBotToken](https://core.telegram.org/bots/api/#replacemanagedbottoken)
+    /// and switch the bot to the new one if it's in the pool. Returns the new token
+    pub async fn replace_managed_bot_token(
+        &self,
+        manager: &Api,
+        bot_id: i64,
+    ) -> Result<String, BotPoolError> {
+        let token = manager
+            .replace_managed_bot_token(bot_id)
+            .wrap()
+            .await
+            .map_err(|error| BotPoolError::ApiError { bot_id, error })?;
+
+        self.set_token(bot_id, token.as_str()).await?;
+        Ok(token)
+    }
+
+    /// Fetch the token of the managed bot with [getManagedBotToken](https://core.telegram.org/bots/api/#getmanagedbottoken),
+    /// switch the bot to it if it's in the pool or add the bot if [`BotPoolConfig::add_managed_bots`] is set
+    ///
+    /// Note: called automatically for `managed_bot` updates received by the pool.
+    /// Returns `None` if the bot is neither in the pool nor added
+    pub async fn sync_managed_bot(
+        &self,
+        manager: &Api,
+        managed_bot: &ManagedBotUpdated,
+    ) -> Result<Option<Arc<Api>>, BotPoolError> {
+        let bot_id = managed_bot.bot.id;
+        if !self.inner.config.add_managed_bots && !self.inner.bots.contains_key(&bot_id) {
+            return Ok(None);
+        }
+
+        let token = manager
+            .get_managed_bot_token(bot_id)
+            .wrap()
+            .await
+            .map_err(|error| BotPoolError::ApiError { bot_id, error })?;
+
+        Box::pin(self.add_bot(token)).await.map(Some)
+    }
+
+    /// Poll all bots of the pool, including ones added later
+    ///
+    /// Notes:
+    /// * Polling of each bot works the same way as [`Api::updates`] does, terminal errors are returned as [`BotPoolError::ApiError`]
+    ///   and the bot is not polled anymore
+    /// * Stops once `cancellation_token` is cancelled, [`BotPool::stop`] is called or receiving is started again
+    #[must_use]
+    pub fn start_polling(&self, cancellation_token: &CancellationToken) -> BotPoolUpdates {
+        let (sender, receiver) = mpsc::channel(MAX_PENDING_UPDATES);
+        let delivery = Delivery {
+            sender,
+            cancellation_token: cancellation_token.child_token(),
+            webhook: None,
+        };
+        self.set_delivery(delivery.clone());
+
+        for bot in &self.inner.bots {
+            self.spawn_polling(*bot.key(), bot.value(), &delivery);
+        }
+
+        BotPoolUpdates {
+            receiver,
+            #[cfg(feature = "webhook")]
+            local_addr: None,
+        }
+    }
+
+    /// Start one [WebhookServer](crate::webhook::WebhookServer) for all bots of the pool and set webhooks of the bots to `{url}/{bot_id}`
+    ///
+    /// Notes:
+    /// * `url` must be the public HTTPS URL which is proxied to [`WebhookConfig::listen_addr`](crate::webhook::WebhookConfig::listen_addr) and [`WebhookConfig::path`](crate::webhook::WebhookConfig::path)
+    /// * Bots added later get their webhooks set by [`BotPool::add_bot`]
+    /// * Stops once `cancellation_token` is cancelled, [`BotPool::stop`] is called or receiving is started again
+    #[cfg(feature = "webhook")]
+    pub async fn listen_webhook(
+        &self,
+        url: impl Into<String>,
+        config: crate::webhook::WebhookConfig,
+        cancellation_token: &CancellationToken,
+    ) -> Result<BotPoolUpdates, BotPoolError> {
+        let url = url.into().trim_end_matches('/').to_owned();
+        let secret_token = config.secret_token.clone();
+        let server = crate::webhook::WebhookServer::bind_per_bot(config).await?;
+        let local_addr = server.local_addr();
+
+        let (sender, receiver) = mpsc::channel(MAX_PENDING_UPDATES);
+        let delivery = Delivery {
+            sender,
+            cancellation_token: cancellation_token.child_token(),
+            webhook: Some((url, secret_token)),
+        };
+        self.set_delivery(delivery.clone());
+
+        let bots = self
+            .inner
+            .bots
+            .iter()
+            .map(|bot| (*bot.key(), bot.value().clone()))
+            .collect::<Vec<_>>();
+        for (bot_id, bot) in bots {
+            if let Err(err) = self.deliver(bot_id, &bot, &delivery).await {
+                self.stop();
+                return Err(err);
+            }
+        }
+
+        tokio::spawn(self.clone().route_webhook(server, delivery));
+
+        Ok(BotPoolUpdates {
+            receiver,
+            local_addr: Some(local_addr),
+        })
+    }
+
+    /// Stop receiving updates, [`BotPoolUpdates`] ends once already received updates are consumed
+    pub fn stop(&self) {
+        let delivery = self
+            .inner
+            .delivery
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(delivery) = delivery {
+            delivery.cancellation_token.cancel();
+        }
+    }
+
+    fn bot(&self, bot_id: i64) -> Option<Arc<PoolBot>> {
+        self.inner.bots.get(&bot_id).map(|bot| bot.clone())
+    }
+
+    fn delivery(&self) -> Option<Delivery> {
+        self.inner
+            .delivery
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn set_delivery(&self, delivery: Delivery) {
+        let previous = self
+            .inner
+            .delivery
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .replace(delivery.clone());
+        if let Some(previous) = previous {
+            previous.cancellation_token.cancel();
+        }
+
+        // The pool holds a sender too, it's dropped on cancellation so the stream can end
+        let pool = self.clone();
+        tokio::spawn(async move {
+            delivery.cancellation_token.cancelled().await;
+
+            let mut current = pool
+                .inner
+                .delivery
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            if current
+                .as_ref()
+                .is_some_and(|current| current.sender.same_channel(&delivery.sender))
+            {
+                *current = None;
+            }
+            drop(current);
+        });
+    }
+
+    async fn replace_token(
+        &self,
+        bot_id: i64,
+        bot: &Arc<PoolBot>,
+        token: ApiToken,
+    ) -> Result<(), BotPoolError> {
+        {
+            let mut current = bot.token.lock().unwrap_or_else(PoisonError::into_inner);
+            if current.leak() == token.leak() {
+                return Ok(());
+            }
+            bot.api.set_token(token.leak());
+            bot.generation.fetch_add(1, Ordering::Relaxed);
+            *current = token;
+        }
+        log::debug!("Token of bot {bot_id} was replaced");
+
+        if let Some(delivery) = self.delivery()
+            && delivery.webhook.is_some()
+        {
+            self.deliver(bot_id, bot, &delivery).await?;
+        }
+        Ok(())
+    }
+
+    /// Start polling the bot or set its webhook
+    async fn deliver(
+        &self,
+        bot_id: i64,
+        bot: &Arc<PoolBot>,
+        delivery: &Delivery,
+    ) -> Result<(), BotPoolError> {
+        let Some((url, secret_token)) = &delivery.webhook else {
+            self.spawn_polling(bot_id, bot, delivery);
+            return Ok(());
+        };
+
+        let mut request = bot
+            .api
+            .set_webhook(format!("{url}/{bot_id}"))
+            .allowed_updates(bot.api.allowed_updates.clone());
+        if let Some(secret_token) = secret_token {
+            request = request.secret_token(secret_token);
+        }
+        Box::pin(request.wrap())
+            .await
+            .map_err(|error| BotPoolError::ApiError { bot_id, error })?;
+        Ok(())
+    }
+
+    fn spawn_polling(&self, bot_id: i64, bot: &Arc<PoolBot>, delivery: &Delivery) {
+        let cancellation_token = delivery.cancellation_token.child_token();
+        let previous = bot
+            .polling
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .replace(cancellation_token.clone());
+        if let Some(previous) = previous {
+            previous.cancel();
+        }
+
+        tokio::spawn(self.clone().poll_bot(
+            bot_id,
+            bot.clone(),
+            delivery.sender.clone(),
+            cancellation_token,
+        ));
+    }
+
+    async fn poll_bot(
+        self,
+        bot_id: i64,
+        bot: Arc<PoolBot>,
+        sender: mpsc::Sender<Result<BotUpdate, BotPoolError>>,
+        cancellation_token: CancellationToken,
+    ) {
+        loop {
+            let generation = bot.generation.load(Ordering::Relaxed);
+
+            let mut error = None;
+            let mut updates = pin!(bot.api.updates(cancellation_token.clone()));
+            while let Some(result) = updates.next().await {
+                match result {
+                    Ok(update) => {
+                        if !Box::pin(self.forward(bot_id, &bot.api, update, &sender)).await {
+                            return;
+                        }
+                    }
+                    Err(err) => error = Some(err),
+                }
+            }
+
+            let Some(error) = error else {
+                return;
+            };
+
+            if let ConogramErrorType::ApiError(TgApiError::Unauthorized(_)) = &error.type_ {
+                if bot.generation.load(Ordering::Relaxed) == generation {
+                    tokio::select! {
+                        () = cancellation_token.cancelled() => return,
+                        () = tokio::time::sleep(TOKEN_REPLACE_GRACE_PERIOD) => {}
+                    }
+                }
+                if bot.generation.load(Ordering::Relaxed) != generation {
+                    log::debug!("Token of bot {bot_id} was replaced, polling is restarted");
+                    continue;
+                }
+            }
+
+            let _ = Box::pin(sender.send(Err(BotPoolError::ApiError { bot_id, error }))).await;
+            return;
+        }
+    }
+
+    #[cfg(feature = "webhook")]
+    async fn route_webhook(self, mut server: crate::webhook::WebhookServer, delivery: Delivery) {
+        loop {
+            let received = tokio::select! {
+                () = delivery.cancellation_token.cancelled() => return,
+                () = delivery.sender.closed() => return,
+                received = server.recv_per_bot() => received,
+            };
+            let Some((bot_id, update)) = received else {
+                return;
+            };
+
+            let Some(api) = self.get(bot_id) else {
+                log::warn!("Webhook update of bot {bot_id}, which is not in the pool, was dropped");
+                continue;
+            };
+            api.preprocess_updates(std::slice::from_ref(&update));
+
+            if !Box::pin(self.forward(bot_id, &api, update, &delivery.sender)).await {
+                return;
+            }
+        }
+    }
+
+    /// Sync managed bots and send the update to [`BotPoolUpdates`]. Returns `false` if it was dropped
+    async fn forward(
+        &self,
+        bot_id: i64,
+        api: &Arc<Api>,
+        update: Update,
+        sender: &mpsc::Sender<Result<BotUpdate, BotPoolError>>,
+    ) -> bool {
+        if let Some(managed_bot) = &update.managed_bot
+            && let Err(err) = self.sync_managed_bot(api, managed_bot).await
+            && Box::pin(sender.send(Err(err))).await.is_err()
+        {
+            return false;
+        }
+
+        let update = BotUpdate {
+            bot_id,
+            api: api.clone(),
+            update,
+        };
+        Box::pin(sender.send(Ok(update))).await.is_ok()
+    }
+}
+
+/// Updates of all bots of a [BotPool], tagged with the bot they were received by
+#[derive(Debug)]
+pub struct BotPoolUpdates {
+    receiver: mpsc::Receiver<Result<BotUpdate, BotPoolError>>,
+
+    #[cfg(feature = "webhook")]
+    local_addr: Option<std::net::SocketAddr>,
+}
+
+impl BotPoolUpdates {
+    /// Wait for the next update. Returns `None` once receiving is stopped
+    pub async fn recv(&mut self) -> Option<Result<BotUpdate, BotPoolError>> {
+        self.receiver.recv().await
+    }
+
+    /// Address the webhook server is actually bound to, `None` when polling
+    #[cfg(feature = "webhook")]
+    #[must_use]
+    pub const fn local_addr(&self) -> Option<std::net::SocketAddr> {
+        self.local_addr
+    }
+}
+
+impl Stream for BotPoolUpdates {
+    type Item = Result<BotUpdate, BotPoolError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
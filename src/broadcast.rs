@@ -0,0 +1,504 @@
+//! Sending the same message to many chats
+//!
+//! ```rust, ignore
+//! let state = Broadcast::new(&api, chat_ids, BroadcastContent::copy_message(&message))
+//!     // Progress is saved here, an interrupted broadcast continues from the saved position
+//!     .state_file("broadcast.json")
+//!     .on_progress(|state| log::info!("{}/{} sent", state.sent, state.total))
+//!     .run()
+//!     .await?;
+//!
+//! remove_users(&state.blocked);
+//! ```
+
+use std::path::PathBuf;
+
+use futures::{StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    api::Api,
+    entities::{
+        message::Message,
+        message_entity::MessageEntity,
+        misc::{chat_id::ChatId, formatting::FormattedText, reply_markup::ReplyMarkup},
+    },
+    errors::{ApiErrorKind, ConogramError, ConogramErrorType, TgApiError},
+    polling::CancellationToken,
+    request::RequestT,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum ContentKind {
+    Text {
+        text: String,
+        entities: Vec<MessageEntity>,
+        parse_mode: Option<String>,
+    },
+    Copy {
+        from_chat_id: ChatId,
+        message_id: i64,
+    },
+}
+
+/// Message sent by the [Broadcast]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BroadcastContent {
+    kind: ContentKind,
+    reply_markup: Option<ReplyMarkup>,
+    disable_notification: bool,
+    protect_content: bool,
+    allow_paid_broadcast: bool,
+}
+
+impl BroadcastContent {
+    const fn with_kind(kind: ContentKind) -> Self {
+        Self {
+            kind,
+            reply_markup: None,
+            disable_notification: false,
+            protect_content: false,
+            allow_paid_broadcast: false,
+        }
+    }
+
+    /// Text message, sent with [sendMessage](https://core.telegram.org/bots/api/#sendmessage)
+    pub fn text(text: impl Into<String>) -> Self {
+        Self::with_kind(ContentKind::Text {
+            text: text.into(),
+            entities: vec![],
+            parse_mode: None,
+        })
+    }
+
+    /// Text message with entities, sent with [sendMessage](https://core.telegram.org/bots/api/#sendmessage)
+    #[must_use]
+    pub fn formatted(text: FormattedText) -> Self {
+        let (text, entities) = text.build();
+        Self::with_kind(ContentKind::Text {
+            text,
+            entities,
+            parse_mode: None,
+        })
+    }
+
+    /// Copy of an existing message, sent with [copyMessage](https://core.telegram.org/bots/api/#copymessage)
+    pub fn copy(from_chat_id: impl Into<ChatId>, message_id: impl Into<i64>) -> Self {
+        Self::with_kind(ContentKind::Copy {
+            from_chat_id: from_chat_id.into(),
+            message_id: message_id.into(),
+        })
+    }
+
+    /// Copy of the `message`, sent with [copyMessage](https://core.telegram.org/bots/api/#copymessage)
+    #[must_use]
+    pub fn copy_message(message: &Message) -> Self {
+        Self::copy(message.chat.id, message.message_id)
+    }
+
+    /// Only applies to [`BroadcastContent::text`]
+    #[must_use]
+    pub fn parse_mode(mut self, value: impl Into<String>) -> Self {
+        if let ContentKind::Text { parse_mode, .. } = &mut self.kind {
+            *parse_mode = Some(value.into());
+        }
+        self
+    }
+
+    #[must_use]
+    pub fn reply_markup(mut self, reply_markup: impl Into<ReplyMarkup>) -> Self {
+        self.reply_markup = Some(reply_markup.into());
+        self
+    }
+
+    #[must_use]
+    pub const fn disable_notification(mut self, disable_notification: bool) -> Self {
+        self.disable_notification = disable_notification;
+        self
+    }
+
+    #[must_use]
+    pub const fn protect_content(mut self, protect_content: bool) -> Self {
+        self.protect_content = protect_content;
+        self
+    }
+
+    /// Send up to 1000 messages per second for a fee, see [sendMessage](https://core.telegram.org/bots/api/#sendmessage)
+    #[must_use]
+    pub const fn allow_paid_broadcast(mut self, allow_paid_broadcast: bool) -> Self {
+        self.allow_paid_broadcast = allow_paid_broadcast;
+        self
+    }
+
+    async fn send(&self, api: &Api, chat_id: ChatId) -> Result<(), ConogramError> {
+        match &self.kind {
+            ContentKind::Text {
+                text,
+                entities,
+                parse_mode,
+            } => {
+                let mut request = api
+                    .send_message(chat_id, text.as_str())
+                    .entities(entities.clone())
+                    .disable_notification(self.disable_notification)
+                    .protect_content(self.protect_content)
+                    .allow_paid_broadcast(self.allow_paid_broadcast);
+                if let Some(parse_mode) = parse_mode {
+                    request = request.parse_mode(parse_mode);
+                }
+                if let Some(reply_markup) = &self.reply_markup {
+                    request = request.reply_markup(reply_markup.clone());
+                }
+                Box::pin(request.wrap()).await?;
+            }
+            ContentKind::Copy {
+                from_chat_id,
+                message_id,
+            } => {
+                let mut request = api
+                    .copy_message(chat_id, from_chat_id.clone(), *message_id)
+                    .disable_notification(self.disable_notification)
+                    .protect_content(self.protect_content)
+                    .allow_paid_broadcast(self.allow_paid_broadcast);
+                if let Some(reply_markup) = &self.reply_markup {
+                    request = request.reply_markup(reply_markup.clone());
+                }
+                Box::pin(request.wrap()).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reason the message was not delivered to a chat
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BroadcastFailure {
+    /// The user has blocked the bot, or the bot was kicked from the chat
+    Blocked,
+
+    /// The user's account was deleted
+    Deactivated,
+
+    ChatNotFound,
+
+    /// The group was upgraded to a supergroup with this id
+    Migrated(i64),
+
+    /// Any other error, e.g. message can't be copied
+    Other(String),
+}
+
+impl BroadcastFailure {
+    /// Classify an error returned for a single chat
+    ///
+    /// Returns `None` for errors which would repeat for every other chat (invalid token, network errors etc.),
+    /// the broadcast is stopped on such errors
+    #[must_use]
+    pub fn classify(err: &ConogramError) -> Option<Self> {
+        let ConogramErrorType::ApiError(TgApiError::Generic(params)) = &err.type_ else {
+            return None;
+        };
+
+        let failure = match params.kind() {
+            ApiErrorKind::ChatMigrated { migrate_to_chat_id } => Self::Migrated(migrate_to_chat_id),
+            ApiErrorKind::UserDeactivated => Self::Deactivated,
+            ApiErrorKind::ChatNotFound => Self::ChatNotFound,
+            _ if params.error_code == 403 => Self::Blocked,
+            _ => Self::Other(params.description.clone().unwrap_or_default()),
+        };
+        Some(failure)
+    }
+}
+
+/// Progress of a [Broadcast], can be saved and passed to [`Broadcast::resume`] to continue it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BroadcastState {
+    /// Number of chats in the list
+    pub total: usize,
+
+    /// Index of the next chat in the list, all chats before it are processed
+    pub position: usize,
+
+    /// Number of successfully sent messages
+    pub sent: usize,
+
+    pub blocked: Vec<ChatId>,
+    pub deactivated: Vec<ChatId>,
+    pub chat_not_found: Vec<ChatId>,
+
+    /// (old chat id, new chat id), message is sent to the new chat as well
+    pub migrated: Vec<(ChatId, i64)>,
+
+    /// Chats that failed with other errors and error descriptions
+    pub failed: Vec<(ChatId, String)>,
+}
+
+impl BroadcastState {
+    #[must_use]
+    pub const fn is_finished(&self) -> bool {
+        self.position >= self.total
+    }
+
+    fn record(&mut self, chat_id: ChatId, result: &ChatResult) {
+        self.position += 1;
+        match result {
+            ChatResult::Sent => self.sent += 1,
+            ChatResult::Failed(failure) => self.record_failure(chat_id, failure.clone()),
+            ChatResult::Migrated(new_chat_id, result) => {
+                self.migrated.push((chat_id, *new_chat_id));
+                match result {
+                    None => self.sent += 1,
+                    Some(failure) => self.record_failure((*new_chat_id).into(), failure.clone()),
+                }
+            }
+        }
+    }
+
+    fn record_failure(&mut self, chat_id: ChatId, failure: BroadcastFailure) {
+        match failure {
+            BroadcastFailure::Blocked => self.blocked.push(chat_id),
+            BroadcastFailure::Deactivated => self.deactivated.push(chat_id),
+            BroadcastFailure::ChatNotFound => self.chat_not_found.push(chat_id),
+            BroadcastFailure::Migrated(new_chat_id) => self.migrated.push((chat_id, new_chat_id)),
+            BroadcastFailure::Other(description) => self.failed.push((chat_id, description)),
+        }
+    }
+}
+
+enum ChatResult {
+    Sent,
+    Failed(BroadcastFailure),
+    /// Sent to the new chat id, with failure if it failed too
+    Migrated(i64, Option<BroadcastFailure>),
+}
+
+/// Broadcast was stopped by an error which is not specific to a chat
+#[derive(Debug, Error)]
+#[error("Broadcast stopped at {}/{}: {error}", state.position, state.total)]
+pub struct BroadcastError {
+    /// Progress at the moment of the error, already saved to [`Broadcast::state_file`]
+    pub state: BroadcastState,
+    pub error: ConogramError,
+}
+
+type ProgressFn<'a> = dyn Fn(&BroadcastState) + Send + Sync + 'a;
+
+/// Sends [BroadcastContent] to every chat in the list
+///
+/// Notes:
+/// * Requests are sent with [`RequestT::wrap`], enable [RateLimiter](crate::rate_limiter::RateLimiter) to avoid flood waits altogether
+/// * To resume a broadcast, the chat list must be the same as in the interrupted run
+/// * With [`Broadcast::concurrency`] > 1, chats after the saved position may have already received the message if the broadcast was stopped by an error
+pub struct Broadcast<'a> {
+    api: &'a Api,
+    chat_ids: Vec<ChatId>,
+    content: BroadcastContent,
+
+    concurrency: usize,
+    state: Option<BroadcastState>,
+    state_file: Option<PathBuf>,
+    save_every: usize,
+    on_progress: Option<Box<ProgressFn<'a>>>,
+    cancellation_token: CancellationToken,
+}
+
+impl std::fmt::Debug for Broadcast<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Broadcast")
+            .field("chat_ids", &self.chat_ids.len())
+            .field("content", &self.content)
+            .field("concurrency", &self.concurrency)
+            .field("state_file", &self.state_file)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> Broadcast<'a> {
+    pub fn new(
+        api: &'a Api,
+        chat_ids: impl IntoIterator<Item = impl Into<ChatId>>,
+        content: BroadcastContent,
+    ) -> Self {
+        Self {
+            api,
+            chat_ids: chat_ids.into_iter().map(Into::into).collect(),
+            content,
+            concurrency: 1,
+            state: None,
+            state_file: None,
+            save_every: 100,
+            on_progress: None,
+            cancellation_token: CancellationToken::new(),
+        }
+    }
+
+    /// Number of messages sent at the same time, 1 by default
+    #[must_use]
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Continue from the previously saved state
+    #[must_use]
+    pub fn resume(mut self, state: BroadcastState) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    /// Save the state to the file every `save_every` chats and when the broadcast stops.
+    /// If the file exists, the broadcast continues from the saved state
+    #[must_use]
+    pub fn state_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.state_file = Some(path.into());
+        self
+    }
+
+    /// How often the state is saved to [`Broadcast::state_file`], every 100 chats by default
+    #[must_use]
+    pub fn save_every(mut self, save_every: usize) -> Self {
+        self.save_every = save_every.max(1);
+        self
+    }
+
+    /// Called after every processed chat
+    #[must_use]
+    pub fn on_progress(mut self, on_progress: impl Fn(&BroadcastState) + Send + Sync + 'a) -> Self {
+        self.on_progress = Some(Box::new(on_progress));
+        self
+    }
+
+    /// Stop the broadcast after the messages being sent are finished, the state is saved
+    #[must_use]
+    pub fn cancellation_token(mut self, cancellation_token: CancellationToken) -> Self {
+        self.cancellation_token = cancellation_token;
+        self
+    }
+
+    /// Run the broadcast until all chats are processed or it's cancelled
+    pub async fn run(mut self) -> Result<BroadcastState, BroadcastError> {
+        let mut state = match self.state.take() {
+            Some(state) => state,
+            None => match self.load_state().await {
+                Ok(state) => state,
+                Err(error) => {
+                    return Err(BroadcastError {
+                        state: BroadcastState::default(),
+                        error,
+                    });
+                }
+            },
+        };
+        if state.total != self.chat_ids.len() {
+            if state.position > 0 {
+                log::warn!(
+                    "Resuming broadcast of {} chats with state of {} chats",
+                    self.chat_ids.len(),
+                    state.total
+                );
+            }
+            state.total = self.chat_ids.len();
+        }
+
+        let api = self.api;
+        let content = &self.content;
+        let remaining = self.chat_ids.iter().skip(state.position).cloned();
+        // New sends are not started after cancellation, but the ones in flight are finished and recorded
+        let results = stream::iter(remaining)
+            .take_until(self.cancellation_token.clone().cancelled_owned())
+            .map(|chat_id| async move {
+                let result = Self::send_to(api, content, chat_id.clone()).await;
+                (chat_id, result)
+            })
+            .buffered(self.concurrency);
+        let mut results = std::pin::pin!(results);
+
+        let mut unsaved = 0;
+        while let Some((chat_id, result)) = results.next().await {
+            match result {
+                Ok(result) => state.record(chat_id, &result),
+                Err(error) => {
+                    self.save_state(&state).await;
+                    return Err(BroadcastError { state, error });
+                }
+            }
+
+            if let Some(on_progress) = &self.on_progress {
+                on_progress(&state);
+            }
+
+            unsaved += 1;
+            if unsaved >= self.save_every {
+                unsaved = 0;
+                self.save_state(&state).await;
+            }
+        }
+
+        self.save_state(&state).await;
+        Ok(state)
+    }
+
+    async fn send_to(
+        api: &Api,
+        content: &BroadcastContent,
+        chat_id: ChatId,
+    ) -> Result<ChatResult, ConogramError> {
+        let failure = match content.send(api, chat_id).await {
+            Ok(()) => return Ok(ChatResult::Sent),
+            Err(err) => BroadcastFailure::classify(&err).ok_or(err)?,
+        };
+
+        let BroadcastFailure::Migrated(new_chat_id) = failure else {
+            return Ok(ChatResult::Failed(failure));
+        };
+
+        match content.send(api, new_chat_id.into()).await {
+            Ok(()) => Ok(ChatResult::Migrated(new_chat_id, None)),
+            Err(err) => {
+                let failure = BroadcastFailure::classify(&err).ok_or(err)?;
+                Ok(ChatResult::Migrated(new_chat_id, Some(failure)))
+            }
+        }
+    }
+
+    /// Starting over after failing to read the state would send the message to the same chats twice, so it's an error
+    async fn load_state(&self) -> Result<BroadcastState, ConogramError> {
+        let Some(path) = &self.state_file else {
+            return Ok(BroadcastState::default());
+        };
+
+        let content = match tokio::fs::read(path).await {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(BroadcastState::default());
+            }
+            Err(err) => return Err(ConogramError::new("broadcast", path, err.into())),
+        };
+
+        serde_json::from_slice(&content)
+            .map_err(|err| ConogramError::new("broadcast", path, err.into()))
+    }
+
+    async fn save_state(&self, state: &BroadcastState) {
+        let Some(path) = &self.state_file else {
+            return;
+        };
+
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+
+        let result = async {
+            tokio::fs::write(&tmp_path, serde_json::to_vec(state)?).await?;
+            tokio::fs::rename(&tmp_path, path).await
+        }
+        .await;
+
+        if let Err(err) = result {
+            log::warn!(
+                "Failed to save broadcast state to {}: {err}",
+                path.display()
+            );
+        }
+    }
+}
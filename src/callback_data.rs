@@ -0,0 +1,248 @@
+//! Typed payloads of inline keyboard callback buttons
+//!
+//! ```rust, ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct Vote {
+//!     poll: u32,
+//!     option: u8,
+//! }
+//!
+//! impl CallbackData for Vote {
+//!     const PREFIX: &'static str = "vote";
+//! }
+//!
+//! // Payloads longer than 64 bytes are kept in the store, the button gets a short id instead
+//! let codec = CallbackCodec::with_store(MemoryCallbackDataStore::new(10_000));
+//! let button = codec.button("Yes", &Vote { poll: 1, option: 0 })?;
+//!
+//! let dispatcher = Dispatcher::new(api).handler(Handler::callback_data(
+//!     codec,
+//!     |api, query, vote: Vote| async move {
+//!         query.snackbar(&api, format!("Voted for {}", vote.option)).await?;
+//!         Ok(())
+//!     },
+//! ));
+//! ```
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Debug,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use serde::{Serialize, de::DeserializeOwned};
+use thiserror::Error;
+
+use crate::entities::{
+    callback_query::CallbackQuery, inline_keyboard_button::InlineKeyboardButton,
+};
+
+/// Max length of [`InlineKeyboardButton::callback_data`] in bytes
+pub const MAX_CALLBACK_DATA_LEN: usize = 64;
+
+/// Separates the prefix from a payload kept in the button
+const INLINE_SEPARATOR: char = ':';
+
+/// Separates the prefix from an id of a payload kept in a [`CallbackDataStore`]
+const STORED_SEPARATOR: char = '#';
+
+const STORED_ID_LEN: usize = 12;
+
+#[derive(Debug, Error)]
+pub enum CallbackDataError {
+    /// Payload (de)serialization errors
+    #[error("{0}")]
+    SerdeError(#[from] serde_json::Error),
+
+    /// Encoded payload doesn't fit into the button and there is no store to keep it
+    #[error("Callback data is {len} bytes long, max is {MAX_CALLBACK_DATA_LEN}")]
+    TooLong { len: usize },
+
+    /// Payload was kept in a store, but it's not there anymore (e.g. evicted or lost on restart)
+    #[error("Stored callback data {0} is not found")]
+    NotFound(String),
+}
+
+/// Payload of callback buttons, encoded as `PREFIX:json`
+///
+/// Keep field names short or use tuple structs, as the 64 bytes limit applies to the whole encoded string
+pub trait CallbackData: Serialize + DeserializeOwned {
+    /// Identifies the payload type, must be unique among payloads of the bot and must not contain `:` or `#`
+    const PREFIX: &'static str;
+
+    /// Encode the payload without a store, fails if it's longer than [`MAX_CALLBACK_DATA_LEN`]
+    fn to_callback_data(&self) -> Result<String, CallbackDataError> {
+        CallbackCodec::new().encode(self)
+    }
+
+    /// Decode the payload kept in the button, returns `None` if `data` has another prefix
+    #[must_use]
+    fn from_callback_data(data: &str) -> Option<Result<Self, CallbackDataError>> {
+        CallbackCodec::new().decode(data)
+    }
+}
+
+/// Storage for payloads which don't fit into buttons
+///
+/// Note: methods are called synchronously while buttons are built and queries are decoded, so they should be cheap
+pub trait CallbackDataStore: Debug + Send + Sync {
+    fn put(&self, id: &str, payload: String);
+
+    /// Returns `None` if there is no payload with this id
+    fn get(&self, id: &str) -> Option<String>;
+}
+
+#[derive(Debug, Default)]
+struct MemoryStoreInner {
+    payloads: HashMap<String, String>,
+    order: VecDeque<String>,
+}
+
+/// Keeps up to `capacity` payloads in memory, the oldest ones are evicted first
+///
+/// Buttons of evicted or pre-restart payloads can't be decoded anymore, see [`CallbackDataError::NotFound`]
+#[derive(Debug)]
+pub struct MemoryCallbackDataStore {
+    capacity: usize,
+    inner: Mutex<MemoryStoreInner>,
+}
+
+impl MemoryCallbackDataStore {
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            inner: Mutex::default(),
+        }
+    }
+}
+
+impl CallbackDataStore for MemoryCallbackDataStore {
+    fn put(&self, id: &str, payload: String) {
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if inner.payloads.insert(id.to_owned(), payload).is_none() {
+            inner.order.push_back(id.to_owned());
+        }
+        while inner.order.len() > self.capacity {
+            if let Some(oldest) = inner.order.pop_front() {
+                inner.payloads.remove(&oldest);
+            }
+        }
+        drop(inner);
+    }
+
+    fn get(&self, id: &str) -> Option<String> {
+        self.inner
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .payloads
+            .get(id)
+            .cloned()
+    }
+}
+
+/// Random alphanumeric id of a stored payload
+fn stored_id() -> String {
+    const ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+    let mut random = uuid::Uuid::new_v4().as_u128();
+    (0..STORED_ID_LEN)
+        .map(|_| {
+            let symbol = ALPHABET[(random % ALPHABET.len() as u128) as usize];
+            random /= ALPHABET.len() as u128;
+            symbol as char
+        })
+        .collect()
+}
+
+/// Encodes and decodes [`CallbackData`] payloads, spilling long ones to an optional [`CallbackDataStore`]. Cheap to clone
+#[derive(Debug, Clone, Default)]
+pub struct CallbackCodec {
+    store: Option<Arc<dyn CallbackDataStore>>,
+}
+
+impl CallbackCodec {
+    /// Codec without a store, long payloads fail with [`CallbackDataError::TooLong`]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Payloads longer than [`MAX_CALLBACK_DATA_LEN`] are put to `store` and encoded as `PREFIX#id`
+    pub fn with_store(store: impl CallbackDataStore + 'static) -> Self {
+        Self {
+            store: Some(Arc::new(store)),
+        }
+    }
+
+    pub fn encode<T: CallbackData>(&self, payload: &T) -> Result<String, CallbackDataError> {
+        let json = serde_json::to_string(payload)?;
+        let data = format!("{}{INLINE_SEPARATOR}{json}", T::PREFIX);
+        if data.len() <= MAX_CALLBACK_DATA_LEN {
+            return Ok(data);
+        }
+
+        let Some(store) = &self.store else {
+            return Err(CallbackDataError::TooLong { len: data.len() });
+        };
+
+        let id = stored_id();
+        let data = format!("{}{STORED_SEPARATOR}{id}", T::PREFIX);
+        if data.len() > MAX_CALLBACK_DATA_LEN {
+            return Err(CallbackDataError::TooLong { len: data.len() });
+        }
+
+        store.put(&id, json);
+        Ok(data)
+    }
+
+    /// Returns `None` if `data` has another prefix
+    #[must_use]
+    pub fn decode<T: CallbackData>(&self, data: &str) -> Option<Result<T, CallbackDataError>> {
+        let rest = data.strip_prefix(T::PREFIX)?;
+
+        if let Some(json) = rest.strip_prefix(INLINE_SEPARATOR) {
+            return Some(serde_json::from_str(json).map_err(Into::into));
+        }
+
+        let id = rest.strip_prefix(STORED_SEPARATOR)?;
+        let json = self
+            .store
+            .as_ref()
+            .and_then(|store| store.get(id))
+            .ok_or_else(|| CallbackDataError::NotFound(id.to_owned()));
+        Some(json.and_then(|json| serde_json::from_str(&json).map_err(Into::into)))
+    }
+
+    /// Decode [`CallbackQuery::data`], returns `None` if there is no data or it has another prefix
+    #[must_use]
+    pub fn decode_query<T: CallbackData>(
+        &self,
+        query: &CallbackQuery,
+    ) -> Option<Result<T, CallbackDataError>> {
+        self.decode(query.data.as_deref()?)
+    }
+
+    /// Whether `data` has the prefix of `T`, without decoding it
+    #[must_use]
+    pub fn matches<T: CallbackData>(data: &str) -> bool {
+        data.strip_prefix(T::PREFIX).is_some_and(|rest| {
+            rest.starts_with(INLINE_SEPARATOR) || rest.starts_with(STORED_SEPARATOR)
+        })
+    }
+
+    /// Callback button with the encoded payload
+    pub fn button<T: CallbackData>(
+        &self,
+        text: impl Into<String>,
+        payload: &T,
+    ) -> Result<InlineKeyboardButton, CallbackDataError> {
+        Ok(InlineKeyboardButton::callback(
+            text,
+            self.encode(payload)?,
+            None,
+            None,
+        ))
+    }
+}
@@ -0,0 +1,182 @@
+//! Recording of Bot API traffic to JSONL cassettes and replaying it without the network
+//!
+//! ```rust, ignore
+//! // Capture a real session
+//! let api = Api::new(ApiConfig::new("BOT_TOKEN", None).record("session.jsonl")?);
+//!
+//! // Replay it in a test, responses are served in the recorded order
+//! let api = Api::new(ApiConfig::new("BOT_TOKEN", None).replay("session.jsonl")?);
+//! ```
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    entities::update::Update,
+    errors::ConogramErrorType,
+    transport::{DownloadFuture, Transport, TransportFuture, TransportRequest},
+};
+
+/// Single recorded request and its response, one line of a cassette
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CassetteEntry {
+    pub method: String,
+
+    /// Request params with default params applied. Uploaded files are not recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<Value>,
+
+    /// Raw response body, replayed unchanged
+    pub response: String,
+}
+
+impl CassetteEntry {
+    /// Updates returned by a recorded `getUpdates` call, e.g. to use them as test fixtures
+    #[must_use]
+    pub fn updates(&self) -> Vec<Update> {
+        if self.method != "getUpdates" {
+            return vec![];
+        }
+
+        serde_json::from_str::<Value>(&self.response)
+            .ok()
+            .and_then(|response| Vec::<Update>::deserialize(response.get("result")?).ok())
+            .unwrap_or_default()
+    }
+}
+
+/// Read all entries of a cassette
+pub fn read_cassette(path: impl AsRef<Path>) -> std::io::Result<Vec<CassetteEntry>> {
+    BufReader::new(File::open(path)?)
+        .lines()
+        .filter(|line| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+/// [Transport] which passes requests to an inner transport and appends them with responses to a cassette
+///
+/// Notes:
+/// * Request URLs are not recorded, so cassettes don't contain the bot token
+/// * File downloads are passed to the inner transport and are not recorded
+#[derive(Debug)]
+pub struct RecordingTransport {
+    inner: Arc<dyn Transport>,
+    cassette: tokio::sync::Mutex<tokio::fs::File>,
+}
+
+impl RecordingTransport {
+    /// Append entries to the cassette at `path`, the file is created if it doesn't exist
+    pub fn new(inner: Arc<dyn Transport>, path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let cassette = File::options().create(true).append(true).open(path)?;
+        Ok(Self {
+            inner,
+            cassette: tokio::sync::Mutex::new(tokio::fs::File::from_std(cassette)),
+        })
+    }
+
+    async fn record(&self, entry: &CassetteEntry) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        let mut cassette = self.cassette.lock().await;
+        cassette.write_all(&line).await?;
+        cassette.flush().await
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn send(&self, request: TransportRequest) -> TransportFuture<'_> {
+        Box::pin(async move {
+            let method = request.method.clone();
+            let params = request.params.clone();
+
+            let response = self.inner.send(request).await?;
+
+            let entry = CassetteEntry {
+                method,
+                params,
+                response: String::from_utf8_lossy(&response).into_owned(),
+            };
+            if let Err(err) = self.record(&entry).await {
+                log::warn!("Failed to record {} to the cassette: {err}", entry.method);
+            }
+
+            Ok(response)
+        })
+    }
+
+    fn download<'a>(
+        &'a self,
+        url: String,
+        max_size: Option<u64>,
+        writer: &'a mut (dyn AsyncWrite + Unpin + Send),
+    ) -> DownloadFuture<'a> {
+        self.inner.download(url, max_size, writer)
+    }
+}
+
+/// [Transport] which serves responses from a cassette in the recorded order, without the network
+///
+/// Notes:
+/// * Requests must come in the recorded order, a request for another method fails with [`ConogramErrorType::TransportError`]
+/// * Params are not compared, so a replay doesn't break when e.g. a message text is changed
+/// * File downloads are not recorded, so they are not supported
+#[derive(Debug)]
+pub struct ReplayTransport {
+    entries: Mutex<VecDeque<CassetteEntry>>,
+}
+
+impl ReplayTransport {
+    #[must_use]
+    pub fn new(entries: impl IntoIterator<Item = CassetteEntry>) -> Self {
+        Self {
+            entries: Mutex::new(entries.into_iter().collect()),
+        }
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self::new(read_cassette(path)?))
+    }
+
+    /// Entries which were not replayed yet
+    #[must_use]
+    pub fn remaining(&self) -> usize {
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    fn next(&self, method: &str) -> Result<CassetteEntry, ConogramErrorType> {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+
+        match entries.front() {
+            None => Err(ConogramErrorType::TransportError(
+                format!("Cassette is exhausted, unexpected {method} request").into(),
+            )),
+            Some(entry) if entry.method != method => Err(ConogramErrorType::TransportError(
+                format!("Cassette expected {} request, got {method}", entry.method).into(),
+            )),
+            Some(_) => Ok(entries.pop_front().expect("Entry was just checked")),
+        }
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn send(&self, request: TransportRequest) -> TransportFuture<'_> {
+        let response = self
+            .next(&request.method)
+            .map(|entry| entry.response.into_bytes());
+        Box::pin(async move { response })
+    }
+}
@@ -0,0 +1,150 @@
+//! Tracking of groups upgraded to supergroups
+//!
+//! ```rust, ignore
+//! let mut api = Api::new(config);
+//! api.set_chat_migrations(Some(ChatMigrations::new().with_migrations(stored_migrations)));
+//!
+//! let mut migrations = api.chat_migrations().unwrap().subscribe();
+//! tokio::spawn(async move {
+//!     while let Ok(ChatMigration { from_chat_id, to_chat_id }) = migrations.recv().await {
+//!         db.update_chat_id(from_chat_id, to_chat_id).await;
+//!     }
+//! });
+//! ```
+
+use dashmap::DashMap;
+use serde_json::Value;
+use tokio::sync::broadcast;
+
+use crate::entities::message::Message;
+
+/// How many migrations are kept for subscribers which are lagging behind
+const SUBSCRIBERS_CAPACITY: usize = 64;
+
+/// Request params which contain chat ids that are rewritten
+const CHAT_ID_PARAMS: [&str; 2] = ["chat_id", "from_chat_id"];
+
+/// A group was upgraded to a supergroup with a new id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChatMigration {
+    pub from_chat_id: i64,
+    pub to_chat_id: i64,
+}
+
+/// Registry of migrated chats, fed by `migrate_to_chat_id` errors and migration service messages
+///
+/// Notes:
+/// * `chat_id` and `from_chat_id` params of requests to migrated groups are replaced with new ids before sending
+/// * Requests sent with [`RequestT::wrap`](crate::request::RequestT::wrap) which fail with `migrate_to_chat_id` are retried once
+///   against the new chat, unless disabled with [`ChatMigrations::retry`]
+#[derive(Debug)]
+pub struct ChatMigrations {
+    migrations: DashMap<i64, i64>,
+    retry: bool,
+    sender: broadcast::Sender<ChatMigration>,
+}
+
+impl Default for ChatMigrations {
+    fn default() -> Self {
+        Self {
+            migrations: DashMap::new(),
+            retry: true,
+            sender: broadcast::channel(SUBSCRIBERS_CAPACITY).0,
+        }
+    }
+}
+
+impl ChatMigrations {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start with known migrations, e.g. loaded from a database
+    #[must_use]
+    pub fn with_migrations(self, migrations: impl IntoIterator<Item = ChatMigration>) -> Self {
+        for migration in migrations {
+            self.migrations
+                .insert(migration.from_chat_id, migration.to_chat_id);
+        }
+        self
+    }
+
+    /// Whether failed requests are retried against the new chat, `true` by default
+    #[must_use]
+    pub const fn retry(mut self, retry: bool) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub(crate) const fn retries(&self) -> bool {
+        self.retry
+    }
+
+    /// Receive migrations as they are registered, lagging subscribers miss the oldest ones
+    #[must_use]
+    pub fn subscribe(&self) -> broadcast::Receiver<ChatMigration> {
+        self.sender.subscribe()
+    }
+
+    /// New id of the migrated group
+    #[must_use]
+    pub fn get(&self, from_chat_id: i64) -> Option<i64> {
+        self.migrations
+            .get(&from_chat_id)
+            .map(|to_chat_id| *to_chat_id)
+    }
+
+    #[must_use]
+    pub fn to_vec(&self) -> Vec<ChatMigration> {
+        self.migrations
+            .iter()
+            .map(|migration| ChatMigration {
+                from_chat_id: *migration.key(),
+                to_chat_id: *migration.value(),
+            })
+            .collect()
+    }
+
+    /// Register a migration, subscribers are notified if it's a new one
+    pub fn insert(&self, from_chat_id: i64, to_chat_id: i64) {
+        if from_chat_id == to_chat_id
+            || self.migrations.insert(from_chat_id, to_chat_id) == Some(to_chat_id)
+        {
+            return;
+        }
+
+        log::debug!("Chat {from_chat_id} was migrated to {to_chat_id}");
+        let _ = self.sender.send(ChatMigration {
+            from_chat_id,
+            to_chat_id,
+        });
+    }
+
+    /// Register a migration from a `migrate_to_chat_id` or `migrate_from_chat_id` service message
+    pub fn cache_message(&self, message: &Message) {
+        if let Some(to_chat_id) = message.migrate_to_chat_id {
+            self.insert(message.chat.id, to_chat_id);
+        }
+        if let Some(from_chat_id) = message.migrate_from_chat_id {
+            self.insert(from_chat_id, message.chat.id);
+        }
+    }
+
+    /// Replace ids of migrated chats in request params
+    pub(crate) fn rewrite_params(&self, method: &str, params: &mut Value) {
+        let Value::Object(object) = params else {
+            return;
+        };
+
+        for param in CHAT_ID_PARAMS {
+            if let Some(value) = object.get_mut(param)
+                && let Some(from_chat_id) = value.as_i64()
+                && let Some(to_chat_id) = self.get(from_chat_id)
+            {
+                log::debug!("Sending {method} to {to_chat_id} instead of migrated {from_chat_id}");
+                *value = to_chat_id.into();
+            }
+        }
+    }
+}
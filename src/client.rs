@@ -2,19 +2,33 @@
 
 use std::{
     collections::{HashMap, hash_map::Entry},
-    str::FromStr,
+    path::Path,
+    sync::{
+        Arc, PoisonError, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Instant,
 };
 
-use reqwest::{Client, RequestBuilder, Url, multipart::Form};
+use reqwest::multipart::Form;
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use serde_json::Value;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
 use crate::{
-    api::ApiConfig,
-    entities::misc::input_file::GetFiles,
+    api::{ApiConfig, ApiToken},
+    chat_migrations::ChatMigrations,
+    entities::misc::input_file::{GetFiles, UPLOAD_BYTES},
     errors::{ConogramError, ConogramErrorType, TgApiError, TgApiErrorParams},
+    metrics::{MetricsSink, RequestMetrics},
+    redact::redact_tokens,
+    server_config::ApiServerConfig,
+    transport::{Transport, TransportRequest},
 };
 
+/// Files larger than this can't be downloaded from the remote Bot API server
+pub const MAX_DOWNLOAD_SIZE: u64 = 20 * 1024 * 1024;
+
 #[derive(Deserialize, Debug)]
 pub(crate) struct TgApiResponse<ReturnValue> {
     pub ok: bool,
@@ -26,18 +40,30 @@ pub(crate) struct TgApiResponse<ReturnValue> {
     pub result: Option<ReturnValue>,
 }
 
-pub(crate) struct TgApiClient {
+/// Base URLs contain the bot token, so they are rebuilt when the token is replaced
+struct BaseUrls {
     base_url: String,
-    http_client: Client,
+    file_base_url: String,
+
+    /// Redacted from errors and logs
+    token: ApiToken,
+}
+
+pub(crate) struct TgApiClient {
+    urls: RwLock<BaseUrls>,
+    transport: Arc<dyn Transport>,
     bot_config: ApiConfig,
 
     default_request_params: HashMap<String, HashMap<String, Value>>,
+    chat_migrations: Option<ChatMigrations>,
+    metrics_sink: Option<Arc<dyn MetricsSink>>,
 }
 
 impl std::fmt::Debug for TgApiClient {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("ApiClient")
             .field("config", &self.bot_config)
+            .field("transport", &self.transport)
             .finish_non_exhaustive()
     }
 }
@@ -45,25 +71,84 @@ impl std::fmt::Debug for TgApiClient {
 impl TgApiClient {
     pub fn new(config: ApiConfig) -> Self {
         Self {
-            base_url: if config.server_config.use_test_env {
-                format!(
-                    "{url}/bot{token}/test",
-                    url = config.server_config.url,
-                    token = config.token.leak()
-                )
-            } else {
-                format!(
-                    "{url}/bot{token}",
-                    url = config.server_config.url,
-                    token = config.token.leak()
-                )
-            },
-            http_client: Client::new(),
+            urls: RwLock::new(Self::build_base_urls(&config.server_config, &config.token)),
+            transport: config.transport.clone(),
             bot_config: config,
             default_request_params: HashMap::new(),
+            chat_migrations: None,
+            metrics_sink: None,
         }
     }
 
+    pub const fn server_config(&self) -> &ApiServerConfig {
+        &self.bot_config.server_config
+    }
+
+    pub const fn chat_migrations(&self) -> Option<&ChatMigrations> {
+        self.chat_migrations.as_ref()
+    }
+
+    pub fn set_chat_migrations(&mut self, chat_migrations: Option<ChatMigrations>) {
+        self.chat_migrations = chat_migrations;
+    }
+
+    pub fn metrics_sink(&self) -> Option<&dyn MetricsSink> {
+        self.metrics_sink.as_deref()
+    }
+
+    pub fn set_metrics_sink(&mut self, metrics_sink: Option<Arc<dyn MetricsSink>>) {
+        self.metrics_sink = metrics_sink;
+    }
+
+    /// Send further requests with `token`, requests which are already sent are not affected
+    pub fn set_token(&self, token: &ApiToken) {
+        let urls = Self::build_base_urls(self.server_config(), token);
+        *self.urls.write().unwrap_or_else(PoisonError::into_inner) = urls;
+    }
+
+    fn build_base_urls(server_config: &ApiServerConfig, token: &ApiToken) -> BaseUrls {
+        BaseUrls {
+            base_url: Self::build_base_url(server_config, token, "bot"),
+            file_base_url: Self::build_base_url(server_config, token, "file/bot"),
+            token: token.leak().into(),
+        }
+    }
+
+    fn build_base_url(server_config: &ApiServerConfig, token: &ApiToken, prefix: &str) -> String {
+        if server_config.use_test_env {
+            format!(
+                "{url}/{prefix}{token}/test",
+                url = server_config.url,
+                token = token.leak()
+            )
+        } else {
+            format!(
+                "{url}/{prefix}{token}",
+                url = server_config.url,
+                token = token.leak()
+            )
+        }
+    }
+
+    fn with_token<T>(&self, f: impl FnOnce(&ApiToken) -> T) -> T {
+        f(&self
+            .urls
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .token)
+    }
+
+    /// [`ConogramError::new`] with the current token redacted
+    fn error(
+        &self,
+        method: &str,
+        params: impl Serialize + std::fmt::Debug,
+        error: ConogramErrorType,
+    ) -> ConogramError {
+        let error = ConogramError::new(method, params, error);
+        self.with_token(|token| error.redact(Some(token)))
+    }
+
     /// # Errors
     /// Fails on ``value`` serialization fail
     pub fn set_default_request_param(
@@ -89,13 +174,46 @@ impl TgApiClient {
         Ok(())
     }
 
-    fn build_url(&self, method: &str) -> Url {
-        reqwest::Url::from_str(&format!(
+    fn build_url(&self, method: &str) -> String {
+        format!(
             "{base_url}/{method}",
-            base_url = self.base_url,
+            base_url = self
+                .urls
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .base_url,
             method = method
-        ))
-        .unwrap()
+        )
+    }
+
+    /// Download a file by its `file_path` from [File](crate::entities::file::File)
+    ///
+    /// Local servers return absolute paths, such files are read from the filesystem
+    pub async fn download_file(
+        &self,
+        file_path: &str,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> Result<u64, ConogramErrorType> {
+        // Only a local server can hand out filesystem paths, otherwise a crafted `File` could read any local file
+        if self.server_config().is_local() && Path::new(file_path).is_absolute() {
+            let mut file = tokio::fs::File::open(file_path).await?;
+            let size = tokio::io::copy(&mut file, writer).await?;
+            writer.flush().await?;
+            return Ok(size);
+        }
+
+        let max_size = (!self.server_config().is_local()).then_some(MAX_DOWNLOAD_SIZE);
+        let url = format!(
+            "{}/{file_path}",
+            self.urls
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .file_base_url
+        );
+        self.transport
+            .download(url, max_size, writer)
+            .await
+            .map_err(|err| self.with_token(|token| err.redact(Some(token))))
     }
 
     fn apply_default_params(&self, method: &str, default_value: &mut Value) {
@@ -104,7 +222,10 @@ impl TgApiClient {
         {
             for (param_name, v) in method_entry {
                 if !object.contains_key(param_name) {
-                    log::debug!("Setting {param_name}={v} in {method}");
+                    log::debug!(
+                        "Setting {param_name}={} in {method}",
+                        redact_tokens(&v.to_string())
+                    );
                     object.insert(param_name.clone(), v.clone());
                 }
             }
@@ -140,27 +261,52 @@ impl TgApiClient {
         Into::<Result<ReturnType, ConogramErrorType>>::into(response)
     }
 
+    /// Send the request and record its [metrics](crate::metrics), `upload_bytes` is the size of uploaded files
     pub async fn method<
         ReturnType: DeserializeOwned + std::fmt::Debug,
         Params: Serialize + Sync + std::fmt::Debug,
     >(
-        method: &str,
-        builder: RequestBuilder,
+        &self,
+        request: TransportRequest,
         params: Option<&Params>,
+        upload_bytes: u64,
     ) -> Result<ReturnType, ConogramError> {
-        let response = match builder.send().await {
-            Ok(r) => r,
-            Err(err) => return Err(ConogramError::new(method, params, err.into())),
-        };
+        let method = request.method.clone();
+
+        let start = Instant::now();
+        let result = self.send(request, params).await;
+
+        if let Some(metrics_sink) = &self.metrics_sink {
+            metrics_sink.record_request(&RequestMetrics {
+                method: &method,
+                duration: start.elapsed(),
+                upload_bytes,
+                error: result.as_ref().err().map(|err| &err.type_),
+            });
+        }
 
-        let response_text = match response.text().await {
-            Ok(rt) => rt,
-            Err(err) => return Err(ConogramError::new(method, params, err.into())),
+        result
+    }
+
+    async fn send<
+        ReturnType: DeserializeOwned + std::fmt::Debug,
+        Params: Serialize + Sync + std::fmt::Debug,
+    >(
+        &self,
+        request: TransportRequest,
+        params: Option<&Params>,
+    ) -> Result<ReturnType, ConogramError> {
+        let method = request.method.clone();
+        let method = method.as_str();
+
+        let response = match self.transport.send(request).await {
+            Ok(r) => r,
+            Err(err) => return Err(self.error(method, params, err)),
         };
 
-        let api_response = match serde_json::from_str::<TgApiResponse<ReturnType>>(&response_text) {
+        let api_response = match serde_json::from_slice::<TgApiResponse<ReturnType>>(&response) {
             Ok(r) => r,
-            Err(err) => return Err(ConogramError::new(method, params, err.into())),
+            Err(err) => return Err(self.error(method, params, err.into())),
         };
 
         match Self::process_api_response(api_response) {
@@ -193,7 +339,7 @@ impl TgApiClient {
                     }
                 }
 
-                Err(ConogramError::new(method, params, err))
+                Err(self.error(method, params, err))
             }
         }
     }
@@ -206,22 +352,36 @@ impl TgApiClient {
         method: &str,
         params: Option<&Params>,
     ) -> Result<ReturnType, ConogramError> {
-        let builder = match params {
+        let value = match params {
             Some(params) => {
                 let mut value: Value = match serde_json::to_value(params) {
                     Ok(v) => v,
-                    Err(err) => return Err(ConogramError::new(method, params, err.into())),
+                    Err(err) => return Err(self.error(method, params, err.into())),
                 };
                 self.apply_default_params(method, &mut value);
+                if let Some(chat_migrations) = &self.chat_migrations {
+                    chat_migrations.rewrite_params(method, &mut value);
+                }
 
-                log::debug!("Calling {method}({})", Self::value_to_string(&value));
+                log::debug!(
+                    "Calling {method}({})",
+                    self.with_token(|token| token
+                        .redact(&Self::value_to_string(&value))
+                        .into_owned())
+                );
 
-                self.http_client.post(self.build_url(method)).json(&value)
+                Some(value)
             }
-            None => self.http_client.post(self.build_url(method)),
+            None => None,
         };
 
-        Self::method(method, builder, params).await
+        let request = TransportRequest {
+            method: method.to_owned(),
+            url: self.build_url(method),
+            params: value,
+            multipart: None,
+        };
+        self.method(request, params, 0).await
     }
 
     pub async fn method_multipart_form<
@@ -232,15 +392,23 @@ impl TgApiClient {
         method: &str,
         params: Option<&Params>,
     ) -> Result<ReturnType, ConogramError> {
-        let builder = match params {
+        let (value, form, upload_bytes) = match params {
             Some(params) => {
                 let mut json_struct: Value = match serde_json::to_value(params) {
                     Ok(v) => v,
-                    Err(err) => return Err(ConogramError::new(method, params, err.into())),
+                    Err(err) => return Err(self.error(method, params, err.into())),
                 };
                 self.apply_default_params(method, &mut json_struct);
+                if let Some(chat_migrations) = &self.chat_migrations {
+                    chat_migrations.rewrite_params(method, &mut json_struct);
+                }
 
-                log::debug!("Calling {method}({})", Self::value_to_string(&json_struct));
+                log::debug!(
+                    "Calling {method}({})",
+                    self.with_token(|token| token
+                        .redact(&Self::value_to_string(&json_struct))
+                        .into_owned())
+                );
 
                 let mut form = Form::new();
                 if let Some(v) = json_struct.as_object() {
@@ -254,20 +422,33 @@ impl TgApiClient {
                     }
                 }
 
-                let form = match params.form(form).await {
+                let (form, upload_bytes) = UPLOAD_BYTES
+                    .scope(AtomicU64::new(0), async {
+                        let form = params.form(form).await;
+                        (
+                            form,
+                            UPLOAD_BYTES.with(|bytes| bytes.load(Ordering::Relaxed)),
+                        )
+                    })
+                    .await;
+                let form = match form {
                     Ok(form) => form,
                     Err(err) => {
-                        return Err(ConogramError::new(method, params, err.into()));
+                        return Err(self.error(method, params, err.into()));
                     }
                 };
 
-                self.http_client
-                    .post(self.build_url(method))
-                    .multipart(form)
+                (Some(json_struct), Some(form), upload_bytes)
             }
-            None => self.http_client.post(self.build_url(method)),
+            None => (None, None, 0),
         };
 
-        Self::method(method, builder, params).await
+        let request = TransportRequest {
+            method: method.to_owned(),
+            url: self.build_url(method),
+            params: value,
+            multipart: form,
+        };
+        self.method(request, params, upload_bytes).await
     }
 }
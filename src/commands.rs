@@ -0,0 +1,175 @@
+//! Parsing of `/command@bot_username arguments` messages into typed enums
+//!
+//! ```rust, ignore
+//! #[derive(BotCommands)]
+//! enum Command {
+//!     #[command(description = "Show help")]
+//!     Help,
+//!
+//!     /// Arguments are parsed with `FromStr`, the last one takes the rest of the text
+//!     #[command(description = "Ban a user for some time")]
+//!     #[command(scope = "all_chat_administrators", description = "Ban a user, e.g. /ban 123 1h spam")]
+//!     Ban { user_id: i64, duration: String, reason: String },
+//!
+//!     #[command(rename = "start", hide)]
+//!     Start(String),
+//! }
+//!
+//! set_bot_commands::<Command>(&api).await?;
+//!
+//! match Command::parse_message(&message, "my_bot") {
+//!     Some(Ok(Command::Ban { user_id, .. })) => {}
+//!     Some(Err(err)) => { message.reply(&api, err.to_string()).await?; }
+//!     _ => {}
+//! }
+//! ```
+
+use std::{fmt::Display, str::FromStr};
+
+pub use conogram_derives::BotCommands;
+use thiserror::Error;
+
+use crate::{
+    api::Api,
+    entities::{
+        bot_command::BotCommand, bot_command_scope::BotCommandScope, message::Message,
+        message_entity::MessageEntityType,
+    },
+    errors::ConogramError,
+    request::RequestT,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseCommandError {
+    #[error("Text is not a command")]
+    NotACommand,
+
+    /// Command is addressed to another bot, contains its username
+    #[error("Command is addressed to @{0}")]
+    OtherBot(String),
+
+    #[error("Unknown command: /{0}")]
+    UnknownCommand(String),
+
+    #[error("Missing argument: {0}")]
+    MissingArgument(&'static str),
+
+    #[error("Invalid argument {argument}: {error}")]
+    InvalidArgument {
+        argument: &'static str,
+        error: String,
+    },
+}
+
+/// Enum of bot commands, use `#[derive(BotCommands)]` to implement
+///
+/// Variant attributes, `#[command(...)]`:
+/// * `rename = "name"`: command name, snake_case variant name by default
+/// * `description = "text"`: description shown by Telegram clients in the default scope
+/// * `scope = "..."`, `description = "text"`: description in one of `default`, `all_private_chats`,
+///   `all_group_chats` or `all_chat_administrators` scopes, the attribute can be repeated
+/// * `hide`: parse the command, but don't list it in [`BotCommands::bot_commands`]
+///
+/// Note: Telegram clients show commands of the most specific scope only, e.g. administrators don't see default scope commands
+/// if `all_chat_administrators` scope is set, so commands must be described in every scope they should appear in
+pub trait BotCommands: Sized {
+    /// Parse the command name (without `/` and `@username`) and its arguments
+    fn parse_command(command: &str, args: &str) -> Result<Self, ParseCommandError>;
+
+    /// Listed commands grouped by scope, to be passed to [`SetMyCommandsRequest`](crate::methods::set_my_commands::SetMyCommandsRequest)
+    fn bot_commands() -> Vec<(BotCommandScope, Vec<BotCommand>)>;
+
+    /// Parse `/command@bot_username arguments`, `bot_username` is the username of this bot without `@`
+    fn parse(text: &str, bot_username: &str) -> Result<Self, ParseCommandError> {
+        let text = text.trim_start();
+        let Some(text) = text.strip_prefix('/') else {
+            return Err(ParseCommandError::NotACommand);
+        };
+
+        let (command, args) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let command = match command.split_once('@') {
+            Some((command, username)) if username.eq_ignore_ascii_case(bot_username) => command,
+            Some((_, username)) => return Err(ParseCommandError::OtherBot(username.to_owned())),
+            None => command,
+        };
+        if command.is_empty() {
+            return Err(ParseCommandError::NotACommand);
+        }
+
+        Self::parse_command(command, args.trim())
+    }
+
+    /// Parse the message text or caption if it starts with a [`MessageEntityType::BotCommand`] entity,
+    /// returns `None` for other messages
+    #[must_use]
+    fn parse_message(
+        message: &Message,
+        bot_username: &str,
+    ) -> Option<Result<Self, ParseCommandError>> {
+        let starts_with_command = message
+            .get_entities()
+            .iter()
+            .any(|entity| entity.type_ == MessageEntityType::BotCommand && entity.offset == 0);
+        if !starts_with_command {
+            return None;
+        }
+
+        Some(Self::parse(message.get_text().as_deref()?, bot_username))
+    }
+}
+
+/// Call `setMyCommands` for every scope listed in [`BotCommands::bot_commands`]
+pub async fn set_bot_commands<C: BotCommands>(api: &Api) -> Result<(), ConogramError> {
+    for (scope, commands) in C::bot_commands() {
+        api.set_my_commands(commands).scope(scope).wrap().await?;
+    }
+    Ok(())
+}
+
+/// Splits command arguments for the derived [`BotCommands::parse_command`]
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct CommandArgs<'a> {
+    rest: &'a str,
+    remaining: usize,
+}
+
+impl<'a> CommandArgs<'a> {
+    #[must_use]
+    pub const fn new(args: &'a str, count: usize) -> Self {
+        Self {
+            rest: args,
+            remaining: count,
+        }
+    }
+
+    /// Parse the next argument, the last one takes the rest of the text
+    pub fn next<T>(&mut self, argument: &'static str) -> Result<T, ParseCommandError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.remaining = self.remaining.saturating_sub(1);
+        let value = if self.remaining == 0 {
+            std::mem::take(&mut self.rest)
+        } else {
+            let (value, rest) = self
+                .rest
+                .split_once(char::is_whitespace)
+                .unwrap_or((self.rest, ""));
+            self.rest = rest.trim_start();
+            value
+        };
+
+        if value.is_empty() {
+            return Err(ParseCommandError::MissingArgument(argument));
+        }
+
+        value
+            .parse()
+            .map_err(|err: T::Err| ParseCommandError::InvalidArgument {
+                argument,
+                error: err.to_string(),
+            })
+    }
+}
@@ -0,0 +1,334 @@
+//! Multi-step conversations, state of which is kept per (chat, user) pair
+//!
+//! ```rust, ignore
+//! #[derive(Serialize, Deserialize)]
+//! enum SignUp {
+//!     Name,
+//!     Age { name: String },
+//! }
+//!
+//! impl DialogueState for SignUp {
+//!     fn timeout(&self) -> Option<Duration> {
+//!         Some(Duration::from_secs(600))
+//!     }
+//! }
+//!
+//! let dialogues = Dialogues::<SignUp>::new(MemoryStorage::new());
+//!
+//! let dispatcher = Dispatcher::new(api)
+//!     // Passes messages to the handlers waiting in Dialogue::prompt()
+//!     .middleware(dialogues.middleware())
+//!     .handler(Handler::message(move |api, message| {
+//!         let dialogues = dialogues.clone();
+//!         async move {
+//!             let Some(dialogue) = dialogues.for_message(&message) else {
+//!                 return Ok(());
+//!             };
+//!             let Some(answer) = dialogue.ask(&api, &message, "What's your name?", Duration::from_secs(60)).await? else {
+//!                 return Ok(());
+//!             };
+//!             dialogue.update(SignUp::Age { name: answer.text.unwrap_or_default() }).await?;
+//!             Ok(())
+//!         }
+//!     }));
+//! ```
+
+mod storage;
+
+use std::{future::IntoFuture, marker::PhantomData, sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use thiserror::Error;
+use tokio::sync::oneshot;
+
+pub use self::storage::{JsonFileStorage, MemoryStorage, StateStorage, StoredState};
+use crate::{
+    api::Api,
+    dispatcher::{HandlerResult, Middleware, Next},
+    entities::{
+        callback_query::CallbackQuery, maybe_inaccessible_message::MaybeInaccessibleMessage,
+        message::Message, update::Update,
+    },
+    errors::ConogramError,
+};
+
+/// Identifies a dialogue: the same user has separate dialogues in different chats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DialogueKey {
+    pub chat_id: i64,
+    pub user_id: i64,
+}
+
+impl DialogueKey {
+    #[must_use]
+    pub const fn new(chat_id: i64, user_id: i64) -> Self {
+        Self { chat_id, user_id }
+    }
+
+    /// `None` if the message has no sender
+    #[must_use]
+    pub fn from_message(message: &Message) -> Option<Self> {
+        let user_id = message.from_id();
+        (user_id != 0).then_some(Self::new(message.chat.id, user_id))
+    }
+
+    /// `None` if the query was sent from an inline message
+    #[must_use]
+    pub fn from_callback_query(callback_query: &CallbackQuery) -> Option<Self> {
+        let chat_id = match callback_query.message.as_deref()? {
+            MaybeInaccessibleMessage::Message(message) => message.chat.id,
+            MaybeInaccessibleMessage::InaccessibleMessage(message) => message.chat.id,
+        };
+        Some(Self::new(chat_id, callback_query.from.id))
+    }
+
+    /// Uses [`Update::effective_chat`] and [`Update::effective_user`]
+    #[must_use]
+    pub fn from_update(update: &Update) -> Option<Self> {
+        Some(Self::new(
+            update.effective_chat()?.id,
+            update.effective_user()?.id,
+        ))
+    }
+}
+
+/// Typed dialogue state, serialized to JSON in the [StateStorage]
+pub trait DialogueState: Serialize + DeserializeOwned + Send + Sync + 'static {
+    /// The dialogue is reset if it stays in this state longer than the timeout
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum DialogueError {
+    /// Error while sending a prompt
+    #[error("{0}")]
+    ApiError(#[from] ConogramError),
+
+    /// State (de)serialization errors
+    #[error("{0}")]
+    SerdeError(#[from] serde_json::Error),
+
+    /// Storage IO errors
+    #[error("{0}")]
+    IO(#[from] std::io::Error),
+}
+
+struct DialoguesInner<St> {
+    storage: St,
+    waiters: DashMap<DialogueKey, oneshot::Sender<Box<Message>>>,
+}
+
+/// Shared dialogue manager, cheap to clone
+pub struct Dialogues<S, St = MemoryStorage> {
+    inner: Arc<DialoguesInner<St>>,
+    _state: PhantomData<fn() -> S>,
+}
+
+impl<S, St> Clone for Dialogues<S, St> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            _state: PhantomData,
+        }
+    }
+}
+
+impl<S, St> std::fmt::Debug for Dialogues<S, St> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Dialogues")
+            .field("waiters", &self.inner.waiters.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: DialogueState, St: StateStorage> Dialogues<S, St> {
+    pub fn new(storage: St) -> Self {
+        Self {
+            inner: Arc::new(DialoguesInner {
+                storage,
+                waiters: DashMap::new(),
+            }),
+            _state: PhantomData,
+        }
+    }
+
+    #[must_use]
+    pub fn get(&self, key: DialogueKey) -> Dialogue<S, St> {
+        Dialogue {
+            dialogues: self.clone(),
+            key,
+        }
+    }
+
+    #[must_use]
+    pub fn for_message(&self, message: &Message) -> Option<Dialogue<S, St>> {
+        DialogueKey::from_message(message).map(|key| self.get(key))
+    }
+
+    #[must_use]
+    pub fn for_callback_query(&self, callback_query: &CallbackQuery) -> Option<Dialogue<S, St>> {
+        DialogueKey::from_callback_query(callback_query).map(|key| self.get(key))
+    }
+
+    /// Pass the message to the dialogue waiting for it in [`Dialogue::prompt`]
+    ///
+    /// Returns the message back if no dialogue is waiting for it
+    #[must_use]
+    pub fn feed(&self, message: Box<Message>) -> Option<Box<Message>> {
+        let Some(key) = DialogueKey::from_message(&message) else {
+            return Some(message);
+        };
+        let Some((_, waiter)) = self.inner.waiters.remove(&key) else {
+            return Some(message);
+        };
+        waiter.send(message).err()
+    }
+
+    /// [Middleware] which [feeds](Dialogues::feed) new messages to waiting dialogues,
+    /// messages consumed by dialogues are not passed to handlers
+    #[must_use]
+    pub fn middleware(&self) -> impl Middleware {
+        let dialogues = self.clone();
+        move |api: Arc<Api>, mut update: Update, next: Next| {
+            let consumed = if let Some(message) = update.message.take() {
+                update.message = dialogues.feed(message);
+                update.message.is_none()
+            } else {
+                false
+            };
+
+            async move {
+                if consumed {
+                    return HandlerResult::Ok(());
+                }
+                next.run(api, update).await
+            }
+        }
+    }
+}
+
+/// Dialogue of a single user in a single chat
+pub struct Dialogue<S, St = MemoryStorage> {
+    dialogues: Dialogues<S, St>,
+    key: DialogueKey,
+}
+
+impl<S, St> Clone for Dialogue<S, St> {
+    fn clone(&self) -> Self {
+        Self {
+            dialogues: self.dialogues.clone(),
+            key: self.key,
+        }
+    }
+}
+
+impl<S, St> std::fmt::Debug for Dialogue<S, St> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Dialogue")
+            .field("key", &self.key)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: DialogueState, St: StateStorage> Dialogue<S, St> {
+    #[must_use]
+    pub const fn key(&self) -> DialogueKey {
+        self.key
+    }
+
+    /// Current state, `None` if the dialogue was not started, has exited or timed out
+    pub async fn get(&self) -> Result<Option<S>, DialogueError> {
+        let storage = &self.dialogues.inner.storage;
+        let Some(stored) = storage.get(self.key).await? else {
+            return Ok(None);
+        };
+
+        if stored.is_expired() {
+            storage.remove(self.key).await?;
+            return Ok(None);
+        }
+
+        Ok(Some(serde_json::from_value(stored.state)?))
+    }
+
+    pub async fn get_or_default(&self) -> Result<S, DialogueError>
+    where
+        S: Default,
+    {
+        Ok(self.get().await?.unwrap_or_default())
+    }
+
+    /// Move the dialogue to `state`, its [`DialogueState::timeout`] starts from now
+    pub async fn update(&self, state: S) -> Result<(), DialogueError> {
+        let timeout = state.timeout();
+        let stored = StoredState::new(serde_json::to_value(state)?, timeout);
+        self.dialogues.inner.storage.set(self.key, stored).await
+    }
+
+    /// Reset the dialogue
+    pub async fn exit(&self) -> Result<(), DialogueError> {
+        self.dialogues.inner.storage.remove(self.key).await
+    }
+
+    /// Send the `request` and wait for the next message in this dialogue
+    ///
+    /// Notes:
+    /// * Returns `None` if no message was received in `timeout`, or another prompt was started in this dialogue
+    /// * Requires [`Dialogues::middleware`] to be registered in the [Dispatcher](crate::dispatcher::Dispatcher)
+    /// * Waiting handler keeps occupying one of [`Dispatcher::concurrency_limit`](crate::dispatcher::Dispatcher::concurrency_limit) slots
+    /// * With [`AckMode::Manual`](crate::offset_store::AckMode::Manual) the handler's update stays unacknowledged while waiting,
+    ///   if 100 updates pile up behind it no newer updates are received, including the awaited message
+    pub async fn prompt<R>(
+        &self,
+        request: R,
+        timeout: Duration,
+    ) -> Result<Option<Box<Message>>, DialogueError>
+    where
+        R: IntoFuture<Output = Result<Message, ConogramError>> + Send,
+        R::IntoFuture: Send,
+    {
+        // Waiter is registered before sending, so a quick answer is not missed
+        let (sender, receiver) = oneshot::channel();
+        self.dialogues.inner.waiters.insert(self.key, sender);
+
+        if let Err(err) = request.await {
+            drop(receiver);
+            self.remove_waiter();
+            return Err(err.into());
+        }
+
+        match tokio::time::timeout(timeout, receiver).await {
+            Ok(Ok(message)) => Ok(Some(message)),
+            Ok(Err(_)) => Ok(None),
+            Err(_) => {
+                self.remove_waiter();
+                Ok(None)
+            }
+        }
+    }
+
+    /// Reply to the `message` with `text` and wait for the answer, see [`Dialogue::prompt`]
+    ///
+    /// Note: may never receive the answer with [`AckMode::Manual`](crate::offset_store::AckMode::Manual), see [`Dialogue::prompt`]
+    pub async fn ask(
+        &self,
+        api: &Api,
+        message: &Message,
+        text: impl Into<String>,
+        timeout: Duration,
+    ) -> Result<Option<Box<Message>>, DialogueError> {
+        self.prompt(message.reply_(api).text(text.into()), timeout)
+            .await
+    }
+
+    fn remove_waiter(&self) {
+        // Don't remove a waiter of a newer prompt
+        self.dialogues
+            .inner
+            .waiters
+            .remove_if(&self.key, |_, waiter| waiter.is_closed());
+    }
+}
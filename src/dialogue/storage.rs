@@ -0,0 +1,173 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use super::{DialogueError, DialogueKey};
+
+/// Dialogue state as it is kept in a [StateStorage]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredState {
+    pub state: serde_json::Value,
+
+    /// Unix time in milliseconds after which the state is discarded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl StoredState {
+    #[must_use]
+    pub fn new(state: serde_json::Value, timeout: Option<Duration>) -> Self {
+        Self {
+            state,
+            expires_at: timeout.map(|timeout| unix_millis(SystemTime::now() + timeout)),
+        }
+    }
+
+    #[must_use]
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= unix_millis(SystemTime::now()))
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Storage of dialogue states, states are stored as JSON values
+///
+/// Note: expired states are discarded by the [Dialogue](super::Dialogue), storages don't have to check [`StoredState::expires_at`]
+pub trait StateStorage: Send + Sync + 'static {
+    fn get(
+        &self,
+        key: DialogueKey,
+    ) -> impl Future<Output = Result<Option<StoredState>, DialogueError>> + Send;
+
+    fn set(
+        &self,
+        key: DialogueKey,
+        state: StoredState,
+    ) -> impl Future<Output = Result<(), DialogueError>> + Send;
+
+    fn remove(&self, key: DialogueKey) -> impl Future<Output = Result<(), DialogueError>> + Send;
+}
+
+/// Keeps states in memory, they are lost on restart
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    states: DashMap<DialogueKey, StoredState>,
+}
+
+impl MemoryStorage {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StateStorage for MemoryStorage {
+    async fn get(&self, key: DialogueKey) -> Result<Option<StoredState>, DialogueError> {
+        Ok(self.states.get(&key).map(|state| state.clone()))
+    }
+
+    async fn set(&self, key: DialogueKey, state: StoredState) -> Result<(), DialogueError> {
+        self.states.insert(key, state);
+        Ok(())
+    }
+
+    async fn remove(&self, key: DialogueKey) -> Result<(), DialogueError> {
+        self.states.remove(&key);
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonFileEntry {
+    #[serde(flatten)]
+    key: DialogueKey,
+    #[serde(flatten)]
+    state: StoredState,
+}
+
+/// Keeps all states in a single JSON file, which is rewritten on every change
+///
+/// Suitable for small bots, states of all dialogues are also kept in memory
+#[derive(Debug)]
+pub struct JsonFileStorage {
+    path: PathBuf,
+    states: Mutex<HashMap<DialogueKey, StoredState>>,
+}
+
+impl JsonFileStorage {
+    /// Load states from `path`, the file is created on the first change if it doesn't exist
+    pub async fn open(path: impl Into<PathBuf>) -> Result<Self, DialogueError> {
+        let path = path.into();
+
+        let states = match tokio::fs::read(&path).await {
+            Ok(content) => serde_json::from_slice::<Vec<JsonFileEntry>>(&content)?
+                .into_iter()
+                .filter(|entry| !entry.state.is_expired())
+                .map(|entry| (entry.key, entry.state))
+                .collect(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Self {
+            path,
+            states: Mutex::new(states),
+        })
+    }
+
+    async fn save(&self, states: &HashMap<DialogueKey, StoredState>) -> Result<(), DialogueError> {
+        let entries: Vec<_> = states
+            .iter()
+            .map(|(key, state)| JsonFileEntry {
+                key: *key,
+                state: state.clone(),
+            })
+            .collect();
+
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+
+        tokio::fs::write(&tmp_path, serde_json::to_vec(&entries)?).await?;
+        tokio::fs::rename(tmp_path, &self.path).await?;
+        Ok(())
+    }
+}
+
+impl StateStorage for JsonFileStorage {
+    async fn get(&self, key: DialogueKey) -> Result<Option<StoredState>, DialogueError> {
+        Ok(self.states.lock().await.get(&key).cloned())
+    }
+
+    async fn set(&self, key: DialogueKey, state: StoredState) -> Result<(), DialogueError> {
+        let mut states = self.states.lock().await;
+        states.insert(key, state);
+        // Lock is held while writing, so older content can't overwrite newer one
+        let result = self.save(&states).await;
+        drop(states);
+        result
+    }
+
+    async fn remove(&self, key: DialogueKey) -> Result<(), DialogueError> {
+        let mut states = self.states.lock().await;
+        let result = if states.remove(&key).is_some() {
+            self.save(&states).await
+        } else {
+            Ok(())
+        };
+        drop(states);
+        result
+    }
+}
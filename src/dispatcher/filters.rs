@@ -0,0 +1,215 @@
+use std::{
+    fmt::Debug,
+    ops::{BitAnd, BitOr, Not},
+    sync::Arc,
+};
+
+use regex::Regex;
+
+use crate::entities::{chat::ChatType, message::Message, update::Update};
+
+type Predicate = dyn Fn(&Update, Option<&str>) -> bool + Send + Sync;
+
+/// Predicate deciding whether a [Handler](super::Handler) should receive the update
+///
+/// Filters can be combined with `&`, `|` and `!`
+#[derive(Clone)]
+pub struct Filter(Arc<Predicate>);
+
+impl Debug for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Filter").finish_non_exhaustive()
+    }
+}
+
+impl Filter {
+    pub fn new(predicate: impl Fn(&Update) -> bool + Send + Sync + 'static) -> Self {
+        Self(Arc::new(move |update, _| predicate(update)))
+    }
+
+    /// Predicate which also gets the bot username, see [`Filter::check_for_bot`]
+    fn with_bot_username(
+        predicate: impl Fn(&Update, Option<&str>) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Self(Arc::new(predicate))
+    }
+
+    /// Filter on [`Update::effective_message`], fails if the update has no message
+    pub fn message(predicate: impl Fn(&Message) -> bool + Send + Sync + 'static) -> Self {
+        Self::new(move |update| update.effective_message().is_some_and(&predicate))
+    }
+
+    /// Check the update without knowing the bot username, so commands addressed to any bot are matched
+    #[must_use]
+    pub fn check(&self, update: &Update) -> bool {
+        self.check_for_bot(update, None)
+    }
+
+    /// Check the update received by the bot with `bot_username`, commands addressed to other bots are not matched.
+    /// [Dispatcher](super::Dispatcher) passes the username returned by `getMe`
+    #[must_use]
+    pub fn check_for_bot(&self, update: &Update, bot_username: Option<&str>) -> bool {
+        (self.0)(update, bot_username)
+    }
+
+    #[must_use]
+    pub fn and(self, other: Self) -> Self {
+        Self::with_bot_username(move |update, bot_username| {
+            self.check_for_bot(update, bot_username) && other.check_for_bot(update, bot_username)
+        })
+    }
+
+    #[must_use]
+    pub fn or(self, other: Self) -> Self {
+        Self::with_bot_username(move |update, bot_username| {
+            self.check_for_bot(update, bot_username) || other.check_for_bot(update, bot_username)
+        })
+    }
+
+    /// [`Update::effective_chat`] is of one of the `chat_types`
+    pub fn chat_type(chat_types: impl IntoIterator<Item = ChatType>) -> Self {
+        let chat_types = chat_types.into_iter().collect::<Vec<_>>();
+        Self::new(move |update| {
+            update
+                .effective_chat()
+                .is_some_and(|chat| chat_types.contains(&chat.type_))
+        })
+    }
+
+    #[must_use]
+    pub fn private() -> Self {
+        Self::chat_type([ChatType::Private])
+    }
+
+    /// Groups and supergroups
+    #[must_use]
+    pub fn group() -> Self {
+        Self::chat_type([ChatType::Group, ChatType::Supergroup])
+    }
+
+    #[must_use]
+    pub fn channel() -> Self {
+        Self::chat_type([ChatType::Channel])
+    }
+
+    /// [`Update::effective_chat`] id is one of `chat_ids`
+    pub fn chat_id(chat_ids: impl IntoIterator<Item = i64>) -> Self {
+        let chat_ids = chat_ids.into_iter().collect::<Vec<_>>();
+        Self::new(move |update| {
+            update
+                .effective_chat()
+                .is_some_and(|chat| chat_ids.contains(&chat.id))
+        })
+    }
+
+    /// [`Update::effective_user`] id or [`Message::from_id`] is one of `sender_ids`
+    pub fn sender_id(sender_ids: impl IntoIterator<Item = i64>) -> Self {
+        let sender_ids = sender_ids.into_iter().collect::<Vec<_>>();
+        Self::new(move |update| {
+            if let Some(user) = update.effective_user() {
+                sender_ids.contains(&user.id)
+            } else if let Some(message) = update.effective_message() {
+                sender_ids.contains(&message.from_id())
+            } else {
+                false
+            }
+        })
+    }
+
+    /// Message has text or caption
+    #[must_use]
+    pub fn has_text() -> Self {
+        Self::message(|message| message.get_text().is_some())
+    }
+
+    /// Message text starts with `/command` or `/command@bot_username`.
+    /// `/command@other_bot` is matched only by [`Filter::check`], which doesn't know the bot username
+    pub fn command(command: impl Into<String>) -> Self {
+        Self::commands([command])
+    }
+
+    /// Message text starts with any of the `commands`, see [`Filter::command`]
+    pub fn commands(commands: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        let commands = commands
+            .into_iter()
+            .map(|c| c.into().trim_start_matches('/').to_lowercase())
+            .collect::<Vec<_>>();
+
+        Self::with_bot_username(move |update, bot_username| {
+            let Some((command, username)) = update.effective_message().and_then(command) else {
+                return false;
+            };
+            if let (Some(username), Some(bot_username)) = (username, bot_username)
+                && !username.eq_ignore_ascii_case(bot_username)
+            {
+                return false;
+            }
+
+            commands.iter().any(|c| c.eq_ignore_ascii_case(command))
+        })
+    }
+
+    /// Message text or caption matches the regex
+    #[must_use]
+    pub fn regex(regex: Regex) -> Self {
+        Self::message(move |message| {
+            message
+                .get_text()
+                .as_ref()
+                .is_some_and(|text| regex.is_match(text))
+        })
+    }
+
+    /// Same as [`Filter::regex`], but compiles the `pattern`
+    pub fn text_regex(pattern: &str) -> Result<Self, regex::Error> {
+        Ok(Self::regex(Regex::new(pattern)?))
+    }
+}
+
+impl BitAnd for Filter {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self::Output {
+        self.and(rhs)
+    }
+}
+
+impl BitOr for Filter {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        self.or(rhs)
+    }
+}
+
+impl Not for Filter {
+    type Output = Self;
+
+    fn not(self) -> Self::Output {
+        Self::with_bot_username(move |update, bot_username| {
+            !self.check_for_bot(update, bot_username)
+        })
+    }
+}
+
+/// Command of the message without `/` and the username it's addressed to, if any
+fn command(message: &Message) -> Option<(&str, Option<&str>)> {
+    let text = message.get_text().as_deref()?;
+    let command = text.split_whitespace().next()?.strip_prefix('/')?;
+
+    Some(
+        command
+            .split_once('@')
+            .map_or((command, None), |(command, username)| {
+                (command, Some(username))
+            }),
+    )
+}
+
+/// Message text starts with a command addressed to a bot, e.g. `/start@bot_username`
+pub fn is_addressed_command(update: &Update) -> bool {
+    update
+        .effective_message()
+        .and_then(command)
+        .is_some_and(|(_, username)| username.is_some())
+}
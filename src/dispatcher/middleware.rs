@@ -0,0 +1,82 @@
+use std::{future::Future, sync::Arc};
+
+use super::{BoxFuture, Handler, HandlerResult, route};
+use crate::{api::Api, entities::update::Update};
+
+/// Wraps every handler call, e.g. for logging, access control or timing
+///
+/// Implemented for `Fn(Arc<Api>, Update, Next) -> impl Future<Output = HandlerResult>` closures.
+/// Middleware may skip calling [`Next::run`] to stop update processing
+pub trait Middleware: Send + Sync + 'static {
+    fn handle(
+        &self,
+        api: Arc<Api>,
+        update: Update,
+        next: Next,
+    ) -> BoxFuture<'static, HandlerResult>;
+}
+
+impl<F, Fut> Middleware for F
+where
+    F: Fn(Arc<Api>, Update, Next) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = HandlerResult> + Send + 'static,
+{
+    fn handle(
+        &self,
+        api: Arc<Api>,
+        update: Update,
+        next: Next,
+    ) -> BoxFuture<'static, HandlerResult> {
+        Box::pin(self(api, update, next))
+    }
+}
+
+/// The rest of the middleware chain, ending with the matching handler
+pub struct Next {
+    middlewares: Arc<Vec<Arc<dyn Middleware>>>,
+    handlers: Arc<Vec<Handler>>,
+    bot_username: Option<Arc<str>>,
+    index: usize,
+}
+
+impl std::fmt::Debug for Next {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Next")
+            .field(
+                "remaining_middlewares",
+                &(self.middlewares.len() - self.index),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+impl Next {
+    pub(crate) const fn new(
+        middlewares: Arc<Vec<Arc<dyn Middleware>>>,
+        handlers: Arc<Vec<Handler>>,
+        bot_username: Option<Arc<str>>,
+    ) -> Self {
+        Self {
+            middlewares,
+            handlers,
+            bot_username,
+            index: 0,
+        }
+    }
+
+    /// Pass the update to the next middleware or to the handler
+    pub fn run(self, api: Arc<Api>, update: Update) -> BoxFuture<'static, HandlerResult> {
+        if let Some(middleware) = self.middlewares.get(self.index).cloned() {
+            middleware.handle(
+                api,
+                update,
+                Self {
+                    index: self.index + 1,
+                    ..self
+                },
+            )
+        } else {
+            route(&self.handlers, api, update, self.bot_username.as_deref())
+        }
+    }
+}
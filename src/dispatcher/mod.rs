@@ -0,0 +1,507 @@
+//! Routing of updates to typed handlers
+//!
+//! ```rust, ignore
+//! let dispatcher = Dispatcher::new(api)
+//!     .concurrency_limit(16)
+//!     .middleware(|api, update, next: Next| async move {
+//!         log::info!("Got update {}", update.update_id);
+//!         next.run(api, update).await
+//!     })
+//!     .handler(
+//!         Handler::message(|api, message| async move {
+//!             message.reply(&api, "Hi!").await?;
+//!             Ok(())
+//!         })
+//!         .filter(Filter::command("start")),
+//!     );
+//!
+//! dispatcher.run_polling().await?;
+//! ```
+
+mod filters;
+mod middleware;
+
+use std::{
+    collections::BTreeSet,
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+
+use futures::{Stream, StreamExt};
+use tokio::sync::{OnceCell, Semaphore};
+
+pub use self::{
+    filters::Filter,
+    middleware::{Middleware, Next},
+};
+use crate::{
+    api::Api,
+    callback_data::{CallbackCodec, CallbackData},
+    entities::{
+        bot_subscription_updated::BotSubscriptionUpdated,
+        business_connection::BusinessConnection,
+        business_messages_deleted::BusinessMessagesDeleted,
+        callback_query::CallbackQuery,
+        chat_boost_removed::ChatBoostRemoved,
+        chat_boost_updated::ChatBoostUpdated,
+        chat_join_request::ChatJoinRequest,
+        chat_member_updated::ChatMemberUpdated,
+        chosen_inline_result::ChosenInlineResult,
+        inline_query::InlineQuery,
+        managed_bot_updated::ManagedBotUpdated,
+        message::Message,
+        message_reaction_count_updated::MessageReactionCountUpdated,
+        message_reaction_updated::MessageReactionUpdated,
+        paid_media_purchased::PaidMediaPurchased,
+        poll::Poll,
+        poll_answer::PollAnswer,
+        pre_checkout_query::PreCheckoutQuery,
+        shipping_query::ShippingQuery,
+        update::{AllowedUpdates, Update},
+    },
+    errors::ConogramError,
+    offset_store::AckMode,
+    polling::CancellationToken,
+};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Any error can be returned from a handler, e.g. [ConogramError] via `?`
+pub type HandlerError = Box<dyn std::error::Error + Send + Sync>;
+pub type HandlerResult = Result<(), HandlerError>;
+
+type HandlerFn =
+    dyn Fn(Arc<Api>, Update) -> Option<BoxFuture<'static, HandlerResult>> + Send + Sync;
+type ErrorHandlerFn = dyn Fn(i64, &HandlerError) + Send + Sync;
+
+/// Update handler, registered for a single update kind
+///
+/// Handler receives the update's content by value, e.g. `Box<Message>` for [`Handler::message`]
+#[derive(Clone)]
+pub struct Handler {
+    kind: Option<AllowedUpdates>,
+    filters: Vec<Filter>,
+    callback: Arc<HandlerFn>,
+}
+
+impl Debug for Handler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Handler")
+            .field("kind", &self.kind)
+            .field("filters", &self.filters.len())
+            .finish_non_exhaustive()
+    }
+}
+
+macro_rules! handler_constructors {
+    ($($name: ident => $kind: ident: $payload: ty),* $(,)?) => {
+        impl Handler {
+            $(
+                #[doc = concat!("Handle [`Update::", stringify!($name), "`] updates")]
+                pub fn $name<F, Fut>(handler: F) -> Self
+                where
+                    F: Fn(Arc<Api>, $payload) -> Fut + Send + Sync + 'static,
+                    Fut: Future<Output = HandlerResult> + Send + 'static,
+                {
+                    Self::with_kind(Some(AllowedUpdates::$kind), move |api, update| {
+                        update
+                            .$name
+                            .map(|payload| Box::pin(handler(api, payload)) as BoxFuture<'static, HandlerResult>)
+                    })
+                }
+            )*
+        }
+    };
+}
+
+handler_constructors!(
+    message => Message: Box<Message>,
+    edited_message => EditedMessage: Box<Message>,
+    channel_post => ChannelPost: Box<Message>,
+    edited_channel_post => EditedChannelPost: Box<Message>,
+    business_connection => BusinessConnection: BusinessConnection,
+    business_message => BusinessMessage: Box<Message>,
+    edited_business_message => EditedBusinessMessage: Box<Message>,
+    deleted_business_messages => DeletedBusinessMessages: BusinessMessagesDeleted,
+    guest_message => GuestMessage: Box<Message>,
+    message_reaction => MessageReaction: MessageReactionUpdated,
+    message_reaction_count => MessageReactionCount: MessageReactionCountUpdated,
+    inline_query => InlineQuery: InlineQuery,
+    chosen_inline_result => ChosenInlineResult: ChosenInlineResult,
+    callback_query => CallbackQuery: CallbackQuery,
+    shipping_query => ShippingQuery: ShippingQuery,
+    pre_checkout_query => PreCheckoutQuery: PreCheckoutQuery,
+    purchased_paid_media => PurchasedPaidMedia: PaidMediaPurchased,
+    poll => Poll: Poll,
+    poll_answer => PollAnswer: PollAnswer,
+    my_chat_member => MyChatMember: ChatMemberUpdated,
+    chat_member => ChatMember: ChatMemberUpdated,
+    chat_join_request => ChatJoinRequest: ChatJoinRequest,
+    chat_boost => ChatBoost: ChatBoostUpdated,
+    removed_chat_boost => RemovedChatBoost: ChatBoostRemoved,
+    managed_bot => ManagedBot: ManagedBotUpdated,
+    subscription => BotSubscriptionUpdated: BotSubscriptionUpdated,
+);
+
+impl Handler {
+    fn with_kind(
+        kind: Option<AllowedUpdates>,
+        callback: impl Fn(Arc<Api>, Update) -> Option<BoxFuture<'static, HandlerResult>>
+        + Send
+        + Sync
+        + 'static,
+    ) -> Self {
+        Self {
+            kind,
+            filters: Vec::new(),
+            callback: Arc::new(callback),
+        }
+    }
+
+    /// Handle updates of any kind
+    pub fn any<F, Fut>(handler: F) -> Self
+    where
+        F: Fn(Arc<Api>, Update) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        Self::with_kind(None, move |api, update| {
+            Some(Box::pin(handler(api, update)))
+        })
+    }
+
+    /// Handle [`Update::callback_query`] updates with [`CallbackData`] of type `T`, matched by [`CallbackData::PREFIX`]
+    ///
+    /// Payloads which fail to decode (e.g. [`CallbackDataError::NotFound`](crate::callback_data::CallbackDataError::NotFound)) are passed to the error handler
+    pub fn callback_data<T, F, Fut>(codec: CallbackCodec, handler: F) -> Self
+    where
+        T: CallbackData + Send + 'static,
+        F: Fn(Arc<Api>, CallbackQuery, T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        Self::with_kind(Some(AllowedUpdates::CallbackQuery), move |api, update| {
+            let query = update.callback_query?;
+            match codec.decode_query::<T>(&query)? {
+                Ok(payload) => Some(Box::pin(handler(api, query, payload))),
+                Err(err) => Some(Box::pin(async move { Err(err.into()) })),
+            }
+        })
+        .filter(Filter::new(|update| {
+            update
+                .callback_query
+                .as_ref()
+                .and_then(|query| query.data.as_deref())
+                .is_some_and(CallbackCodec::matches::<T>)
+        }))
+    }
+
+    /// Handler will be called only if all of its filters pass
+    #[must_use]
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    /// Update kind this handler is registered for, `None` for [`Handler::any`]
+    #[must_use]
+    pub const fn kind(&self) -> Option<AllowedUpdates> {
+        self.kind
+    }
+
+    fn matches(
+        &self,
+        kind: Option<AllowedUpdates>,
+        update: &Update,
+        bot_username: Option<&str>,
+    ) -> bool {
+        (self.kind.is_none() || self.kind == kind)
+            && self
+                .filters
+                .iter()
+                .all(|filter| filter.check_for_bot(update, bot_username))
+    }
+}
+
+/// Passes the update to the first matching handler, in order of registration
+pub(crate) fn route(
+    handlers: &[Handler],
+    api: Arc<Api>,
+    update: Update,
+    bot_username: Option<&str>,
+) -> BoxFuture<'static, HandlerResult> {
+    let kind = update.kind();
+
+    for handler in handlers {
+        if handler.matches(kind, &update, bot_username) {
+            let update_id = update.update_id;
+            if let Some(future) = (handler.callback)(api, update) {
+                return future;
+            }
+
+            log::warn!("Handler matched update {update_id}, but it has no content");
+            return Box::pin(async { Ok(()) });
+        }
+    }
+
+    log::debug!("No handler for update {} ({kind:?})", update.update_id);
+    Box::pin(async { Ok(()) })
+}
+
+/// Receives updates and runs matching [Handler]s concurrently, wrapping them in [Middleware]s
+///
+/// Notes:
+/// * Only the first matching handler is called for each update
+/// * Middlewares are called in order of registration, the first one is the outermost
+/// * Update kinds must be enabled via [`Api::set_allowed_updates`] before passing `api` to the dispatcher
+/// * The bot username is requested via `getMe` once a command addressed to a bot is received, so [`Filter::command`]
+///   doesn't match commands addressed to other bots
+pub struct Dispatcher {
+    api: Arc<Api>,
+
+    handlers: Arc<Vec<Handler>>,
+    middlewares: Arc<Vec<Arc<dyn Middleware>>>,
+    error_handler: Arc<ErrorHandlerFn>,
+
+    concurrency_limit: usize,
+    semaphore: Arc<Semaphore>,
+
+    in_flight: Arc<InFlightUpdates>,
+
+    bot_username: OnceCell<Arc<str>>,
+}
+
+/// Tracks updates being handled, so only updates below the oldest unfinished one are acknowledged
+#[derive(Debug, Default)]
+struct InFlightUpdates {
+    /// (ids of updates being handled, max dispatched update id)
+    state: Mutex<(BTreeSet<i64>, Option<i64>)>,
+}
+
+impl InFlightUpdates {
+    fn start(self: &Arc<Self>, api: Arc<Api>, update_id: i64) -> InFlightGuard {
+        let mut state = self
+            .state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        state.0.insert(update_id);
+        state.1 = state.1.max(Some(update_id));
+        drop(state);
+
+        InFlightGuard {
+            api,
+            in_flight: self.clone(),
+            update_id,
+        }
+    }
+
+    fn finish(&self, api: &Api, update_id: i64) {
+        let ack_up_to = {
+            let mut state = self
+                .state
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+            state.0.remove(&update_id);
+            match state.0.first() {
+                Some(oldest_in_flight) => Some(oldest_in_flight - 1),
+                None => state.1,
+            }
+        };
+
+        if let Some(ack_up_to) = ack_up_to {
+            api.ack_update(ack_up_to);
+        }
+    }
+}
+
+/// Marks the update as finished on drop, even if the handler panicked
+struct InFlightGuard {
+    api: Arc<Api>,
+    in_flight: Arc<InFlightUpdates>,
+    update_id: i64,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.in_flight.finish(&self.api, self.update_id);
+    }
+}
+
+impl Debug for Dispatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Dispatcher")
+            .field("api", &self.api)
+            .field("handlers", &self.handlers)
+            .field("middlewares", &self.middlewares.len())
+            .field("concurrency_limit", &self.concurrency_limit)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Dispatcher {
+    /// Default number of handlers running at the same time
+    pub const DEFAULT_CONCURRENCY_LIMIT: usize = 32;
+
+    pub fn new(api: impl Into<Arc<Api>>) -> Self {
+        Self {
+            api: api.into(),
+            handlers: Arc::default(),
+            middlewares: Arc::default(),
+            error_handler: Arc::new(|update_id, err| {
+                log::error!("Handler for update {update_id} failed: {err}");
+            }),
+            concurrency_limit: Self::DEFAULT_CONCURRENCY_LIMIT,
+            semaphore: Arc::new(Semaphore::new(Self::DEFAULT_CONCURRENCY_LIMIT)),
+            in_flight: Arc::default(),
+            bot_username: OnceCell::new(),
+        }
+    }
+
+    #[must_use]
+    pub const fn api(&self) -> &Arc<Api> {
+        &self.api
+    }
+
+    /// Register a handler. Handlers are checked in order of registration
+    #[must_use]
+    pub fn handler(mut self, handler: Handler) -> Self {
+        Arc::make_mut(&mut self.handlers).push(handler);
+        self
+    }
+
+    /// Register a middleware, which wraps every handler call
+    #[must_use]
+    pub fn middleware(mut self, middleware: impl Middleware) -> Self {
+        Arc::make_mut(&mut self.middlewares).push(Arc::new(middleware));
+        self
+    }
+
+    /// Maximum number of updates handled at the same time, [`Dispatcher::dispatch`] waits while the limit is reached
+    #[must_use]
+    pub fn concurrency_limit(mut self, limit: usize) -> Self {
+        self.concurrency_limit = limit.max(1);
+        self.semaphore = Arc::new(Semaphore::new(self.concurrency_limit));
+        self
+    }
+
+    /// Called with `update_id` for every error returned by handlers and middlewares. By default errors are logged
+    #[must_use]
+    pub fn error_handler(
+        mut self,
+        error_handler: impl Fn(i64, &HandlerError) + Send + Sync + 'static,
+    ) -> Self {
+        self.error_handler = Arc::new(error_handler);
+        self
+    }
+
+    /// Spawn handling of the update. Waits only if the concurrency limit is reached
+    ///
+    /// In [`AckMode::Manual`](crate::offset_store::AckMode::Manual) the update is acknowledged after its handler
+    /// (successful or not) and handlers of all preceding updates are finished
+    pub async fn dispatch(&self, update: Update) {
+        let Ok(permit) = self.semaphore.clone().acquire_owned().await else {
+            return;
+        };
+
+        let bot_username = self.bot_username(&update).await;
+        let next = Next::new(
+            self.middlewares.clone(),
+            self.handlers.clone(),
+            bot_username,
+        );
+        let api = self.api.clone();
+        let error_handler = self.error_handler.clone();
+        let update_id = update.update_id;
+        let in_flight = self.in_flight.start(api.clone(), update_id);
+
+        tokio::spawn(async move {
+            if let Err(err) = next.run(api, update).await {
+                error_handler(update_id, &err);
+            }
+            drop(in_flight);
+            drop(permit);
+        });
+    }
+
+    /// Bot username for command filters, requested only once a command addressed to a bot is received
+    async fn bot_username(&self, update: &Update) -> Option<Arc<str>> {
+        if let Some(bot_username) = self.bot_username.get() {
+            return Some(bot_username.clone());
+        }
+        if !filters::is_addressed_command(update) {
+            return None;
+        }
+
+        match self
+            .bot_username
+            .get_or_try_init(|| async {
+                let me = self.api.get_me().await?;
+                Ok::<_, ConogramError>(me.username.unwrap_or_default().into())
+            })
+            .await
+        {
+            Ok(bot_username) => Some(bot_username.clone()),
+            Err(err) => {
+                log::warn!(
+                    "Failed to get the bot username, commands addressed to any bot are matched: {err}"
+                );
+                None
+            }
+        }
+    }
+
+    /// Wait until all spawned handlers are finished
+    pub async fn wait_idle(&self) {
+        if let Ok(permits) = self
+            .semaphore
+            .acquire_many(self.concurrency_limit as u32)
+            .await
+        {
+            drop(permits);
+        }
+    }
+
+    /// Dispatch updates from the stream until it ends, e.g. from [WebhookServer](crate::webhook::WebhookServer)
+    pub async fn run(&self, updates: impl Stream<Item = Update>) {
+        let mut updates = std::pin::pin!(updates);
+        while let Some(update) = updates.next().await {
+            Box::pin(self.dispatch(update)).await;
+        }
+        self.wait_idle().await;
+    }
+
+    /// Dispatch updates from [`Api::updates`] until a terminal polling error occurs
+    pub async fn run_polling(&self) -> Result<(), ConogramError> {
+        Box::pin(self.run_polling_until(CancellationToken::new())).await
+    }
+
+    /// Same as [`Dispatcher::run_polling`], but stops polling once `cancellation_token` is cancelled
+    ///
+    /// Returns after all running handlers are finished. With [`AckMode::Manual`] the offset is committed once more
+    /// after that, so updates acknowledged by handlers which were running at shutdown are not received again
+    pub async fn run_polling_until(
+        &self,
+        cancellation_token: CancellationToken,
+    ) -> Result<(), ConogramError> {
+        let mut updates = Box::pin(self.api.updates(cancellation_token));
+        while let Some(update) = updates.next().await {
+            match update {
+                Ok(update) => Box::pin(self.dispatch(update)).await,
+                Err(err) => {
+                    self.wait_idle().await;
+                    return Err(err);
+                }
+            }
+        }
+
+        self.wait_idle().await;
+
+        if self.api.ack_mode() == AckMode::Manual {
+            log::debug!("Handlers are finished, committing updates offset");
+            if let Err(err) = self.api.commit_updates_offset().await {
+                log::warn!("Failed to commit updates offset: {err}");
+            }
+        }
+        Ok(())
+    }
+}
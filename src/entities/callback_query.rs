@@ -35,7 +35,11 @@ pub struct CallbackQuery {
 
 // Divider: all content below this line will be preserved after code regen
 use super::message::Message;
-use crate::{api::Api, methods::answer_callback_query::AnswerCallbackQueryRequest};
+use crate::{
+    api::Api,
+    callback_data::{CallbackData, CallbackDataError},
+    methods::answer_callback_query::AnswerCallbackQueryRequest,
+};
 
 impl CallbackQuery {
     /// Use this method to send answers to callback queries sent from [inline keyboards](https://core.telegram.org/bots/features#inline-keyboards). The answer will be displayed to the user as a notification at the top of the chat screen or as an alert. On success, *True* is returned.
@@ -86,4 +90,12 @@ impl CallbackQuery {
     pub fn message(&self) -> Option<&Message> {
         self.message.as_ref().and_then(|m| m.as_ref().into())
     }
+
+    /// Decode [`CallbackQuery::data`] encoded by [`CallbackData::to_callback_data`], returns `None` if there is no data or it's another payload type
+    ///
+    /// Use [`CallbackCodec::decode_query`](crate::callback_data::CallbackCodec::decode_query) for payloads kept in a store
+    #[must_use]
+    pub fn decode<T: CallbackData>(&self) -> Option<Result<T, CallbackDataError>> {
+        T::from_callback_data(self.data.as_deref()?)
+    }
 }
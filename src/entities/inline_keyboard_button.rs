@@ -73,6 +73,7 @@ pub struct InlineKeyboardButton {
 }
 
 // Divider: all content below this line will be preserved after code regen
+use crate::callback_data::{CallbackData, CallbackDataError};
 
 impl InlineKeyboardButton {
     /// Pressing the button will insert the bot's username and the specified inline query in the current chat's input field. May be empty, in which case only the bot's username will be inserted.
@@ -157,6 +158,23 @@ impl InlineKeyboardButton {
         }
     }
 
+    /// Data will be sent in a callback query to the bot when the button is pressed, `payload` must be encoded into 64 bytes at most
+    ///
+    /// Use [`CallbackCodec::button`](crate::callback_data::CallbackCodec::button) to keep longer payloads in a store
+    pub fn typed_callback(
+        text: impl Into<String>,
+        payload: &impl CallbackData,
+        style: Option<KeyboardButtonStyle>,
+        icon_custom_emoji_id: Option<String>,
+    ) -> Result<Self, CallbackDataError> {
+        Ok(Self::callback(
+            text,
+            payload.to_callback_data()?,
+            style,
+            icon_custom_emoji_id,
+        ))
+    }
+
     /// The button that copies the specified text to the clipboard
     pub fn copy_text(
         text: impl Into<String>,
@@ -523,6 +523,8 @@ pub struct Message {
 
 // Divider: all content below this line will be preserved after code regen
 
+use tokio::io::AsyncWrite;
+
 use super::{
     input_media::InputMedia, misc::formatting::FormattedText, reaction_type::ReactionType,
 };
@@ -951,6 +953,21 @@ impl Message {
         }
     }
 
+    /// Download the file attached to the message (see [`Message::file_id`]) into `writer`.
+    /// Returns the number of downloaded bytes, or `None` if the message has no file
+    pub async fn download(
+        &self,
+        api: &Api,
+        writer: &mut (impl AsyncWrite + Unpin + Send),
+    ) -> Result<Option<u64>, ConogramError> {
+        let Some(file_id) = self.file_id() else {
+            return Ok(None);
+        };
+
+        let file = api.get_file(file_id).wrap().await?;
+        api.download_file(&file, writer).await.map(Some)
+    }
+
     /// Internal conogram method. Returns `Ok(false)` instead of `Err` if the message can't be deleted
     pub async fn delete_exp<'a>(&'a self, api: &'a Api) -> Result<bool, ConogramError> {
         if self.is_ephemeral() {
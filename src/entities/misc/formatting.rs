@@ -1,7 +1,4 @@
-use std::{
-    fmt::Display,
-    ops::{Range, RangeBounds},
-};
+use std::{fmt::Display, ops::Range};
 
 use crate::{
     entities::{
@@ -32,12 +29,17 @@ impl<T: TgChat> FormatMention for T {
     }
 }
 
-fn ranges_intersect<T1, T2>(one: Range<T1>, two: Range<T2>) -> bool
-where
-    T2: PartialOrd<T1>,
-    T1: PartialOrd<T2>,
-{
-    two.contains(&one.start) || two.contains(&one.end)
+/// Byte index of the char at `utf16_offset`, or of the next char if the offset is inside a surrogate pair
+fn utf16_to_byte_index(text: &str, utf16_offset: usize) -> usize {
+    let mut pos = 0;
+    for (i, c) in text.char_indices() {
+        if pos >= utf16_offset {
+            return i;
+        }
+        pos += c.len_utf16();
+    }
+
+    text.len()
 }
 
 pub trait Utf16Len {
@@ -136,35 +138,27 @@ impl FormattedText {
         self
     }
 
+    /// Part of the text in `range` of utf-16 codeunits, entities crossing the range bounds are cut to fit it
     #[must_use]
     pub fn slice(&self, range: impl Into<Range<usize>>) -> Self {
-        let mut range: Range<usize> = range.into();
-        range.end = std::cmp::min(range.end, self.len_utf16);
-        range.start = std::cmp::max(range.start, 0);
-
-        let new_text = self.text[range.clone()].to_string();
-
-        let mut new_entities = self
-            .entities
-            .iter()
-            .filter(|ent| {
-                ranges_intersect(
-                    ent.offset as usize..(ent.offset + ent.length) as usize,
-                    range.clone(),
-                )
-                // TODO: убедиться что похуй на бот команды и всякие хештеги
-                // && ent.type_ != MessageEntityType::BotCommand
+        let range: Range<usize> = range.into();
+        let end = range.end.min(self.len_utf16);
+        let start = range.start.min(end);
+
+        let new_text = self.text
+            [utf16_to_byte_index(&self.text, start)..utf16_to_byte_index(&self.text, end)]
+            .to_string();
+
+        let new_entities = self.entities.iter().filter_map(|ent| {
+            let ent_start = ent.offset.max(start as i64);
+            let ent_end = (ent.offset + ent.length).min(end as i64);
+
+            (ent_end > ent_start).then(|| MessageEntity {
+                offset: ent_start - start as i64,
+                length: ent_end - ent_start,
+                ..ent.clone()
             })
-            .cloned()
-            .collect::<Vec<_>>();
-
-        for ent in &mut new_entities {
-            let offset = std::cmp::max(ent.offset, range.start as i64);
-            let end = std::cmp::min(ent.offset + ent.length, range.end as i64);
-
-            ent.offset = offset - range.start as i64;
-            ent.length = end - offset;
-        }
+        });
 
         Self::with_text(new_text, new_entities)
     }
@@ -1,4 +1,8 @@
-use std::{io, path::PathBuf};
+use std::{
+    io,
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 use reqwest::multipart::{Form, Part};
 use serde::Serialize;
@@ -72,6 +76,15 @@ impl InputFile {
     }
 }
 
+tokio::task_local! {
+    /// Size of local files added to forms, counted for [metrics](crate::metrics)
+    pub(crate) static UPLOAD_BYTES: AtomicU64;
+}
+
+fn count_upload_bytes(bytes: u64) {
+    let _ = UPLOAD_BYTES.try_with(|upload_bytes| upload_bytes.fetch_add(bytes, Ordering::Relaxed));
+}
+
 // TODO: allow borrowing
 type FileContents = Vec<u8>;
 #[derive(Debug, PartialEq, Eq)]
@@ -123,8 +136,10 @@ impl LocalFile {
         let file_name = self.get_name();
         if let Some(path) = &self.path {
             let file = File::open(path).await?;
+            count_upload_bytes(file.metadata().await?.len());
             Ok(Part::stream(file).file_name(file_name))
         } else if let Some(data) = &self.data {
+            count_upload_bytes(data.len() as u64);
             Ok(Part::bytes(data.clone()).file_name(file_name))
         } else {
             panic!("path or data must be set for LocalFile")
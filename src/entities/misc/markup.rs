@@ -0,0 +1,1144 @@
+//! Conversion of [`FormattedText`] to and from Telegram's HTML and MarkdownV2 markup
+//!
+//! See <https://core.telegram.org/bots/api#formatting-options>
+
+use std::cmp::Reverse;
+
+use thiserror::Error;
+
+use super::formatting::{FormattedText, Utf16Len};
+use crate::entities::{
+    message_entity::{MessageEntity, MessageEntityType},
+    user::User,
+};
+
+/// Characters which must be escaped in MarkdownV2 outside of code entities and link URLs
+const MARKDOWN_V2_RESERVED: &str = "_*[]()~`>#+-=|{}.!";
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseMarkupError {
+    #[error(
+        "Character '{character}' is reserved and must be escaped with the preceding '\\' at byte {offset}"
+    )]
+    ReservedCharacter { character: char, offset: usize },
+
+    /// Entity or tag is not closed, contains its name
+    #[error("Can't find end of {entity} at byte {offset}")]
+    Unclosed { entity: String, offset: usize },
+
+    #[error("Unexpected end tag </{tag}> at byte {offset}")]
+    UnexpectedEndTag { tag: String, offset: usize },
+
+    #[error("Unsupported tag <{tag}> at byte {offset}")]
+    UnsupportedTag { tag: String, offset: usize },
+
+    /// Malformed tag, attribute or URL, contains a description of what's wrong
+    #[error("Invalid {what} at byte {offset}")]
+    Invalid { what: &'static str, offset: usize },
+}
+
+impl FormattedText {
+    /// Text with entities as Telegram HTML, e.g. to resend a received message with `parse_mode = HTML`
+    #[must_use]
+    pub fn to_html(&self) -> String {
+        render(self.get_text(), self.get_entities(), &mut HtmlWriter)
+    }
+
+    /// Text with entities as Telegram MarkdownV2, e.g. to resend a received message with `parse_mode = MarkdownV2`
+    ///
+    /// Note: MarkdownV2 can't express entities nested into code, such entities are skipped
+    #[must_use]
+    pub fn to_markdown_v2(&self) -> String {
+        render(
+            self.get_text(),
+            self.get_entities(),
+            &mut MarkdownV2Writer::default(),
+        )
+    }
+
+    /// Parse Telegram HTML locally, with the same rules Telegram applies to `parse_mode = HTML`
+    pub fn from_html(html: &str) -> Result<Self, ParseMarkupError> {
+        HtmlParser::default().parse(html)
+    }
+
+    /// Parse Telegram MarkdownV2 locally, with the same rules Telegram applies to `parse_mode = MarkdownV2`
+    pub fn from_markdown_v2(markdown: &str) -> Result<Self, ParseMarkupError> {
+        MarkdownV2Parser::default().parse(markdown)
+    }
+}
+
+fn mention_url(user: Option<&User>) -> String {
+    format!("tg://user?id={}", user.map_or(0, |user| user.id))
+}
+
+fn mention_entity(url: &str) -> Option<MessageEntity> {
+    let id = url.strip_prefix("tg://user?id=")?.parse().ok()?;
+    Some(MessageEntity {
+        type_: MessageEntityType::TextMention,
+        user: Some(User {
+            id,
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+}
+
+fn link_entity(url: String) -> Option<MessageEntity> {
+    if url.is_empty() {
+        return None;
+    }
+
+    mention_entity(&url).or_else(|| {
+        Some(MessageEntity {
+            type_: MessageEntityType::TextLink,
+            url: Some(url),
+            ..Default::default()
+        })
+    })
+}
+
+const fn is_code(entity: &MessageEntity) -> bool {
+    matches!(
+        entity.type_,
+        MessageEntityType::Code | MessageEntityType::Pre
+    )
+}
+
+/// Entities which are set by markup, others (e.g. mentions, hashtags) are detected by Telegram from the text
+const fn is_rendered(entity: &MessageEntity) -> bool {
+    use MessageEntityType as T;
+
+    matches!(
+        entity.type_,
+        T::Bold
+            | T::Italic
+            | T::Underline
+            | T::Strikethrough
+            | T::Spoiler
+            | T::Blockquote
+            | T::ExpandableBlockquote
+            | T::Code
+            | T::Pre
+            | T::TextLink
+            | T::TextMention
+            | T::CustomEmoji
+            | T::DateTime
+    )
+}
+
+trait MarkupWriter {
+    fn open(&mut self, out: &mut String, entity: &MessageEntity);
+    fn close(&mut self, out: &mut String, entity: &MessageEntity);
+    fn write_char(&mut self, out: &mut String, c: char, in_code: bool);
+}
+
+/// Walks the text, opening and closing entities at their utf-16 offsets.
+/// Overlapping entities are split, so the markup is always properly nested
+fn render(text: &str, entities: &[MessageEntity], writer: &mut impl MarkupWriter) -> String {
+    let text_len = text.utf16_codeunits() as i64;
+    let end = |entity: &MessageEntity| (entity.offset + entity.length).min(text_len);
+
+    let mut entities = entities
+        .iter()
+        .filter(|entity| {
+            is_rendered(entity)
+                && entity.length > 0
+                && entity.offset >= 0
+                && entity.offset < text_len
+        })
+        .collect::<Vec<_>>();
+    entities.sort_by_key(|entity| (entity.offset, Reverse(entity.length)));
+
+    let mut out = String::with_capacity(text.len());
+    let mut open: Vec<&MessageEntity> = Vec::new();
+    let mut next = 0;
+    let mut pos = 0;
+
+    for c in text.chars().map(Some).chain([None]) {
+        while let Some(i) = open.iter().rposition(|entity| end(entity) <= pos) {
+            let above = open.split_off(i + 1);
+            for entity in above.iter().rev() {
+                writer.close(&mut out, entity);
+            }
+            writer.close(&mut out, open.pop().expect("Index was just found"));
+
+            for entity in above {
+                if end(entity) > pos {
+                    writer.open(&mut out, entity);
+                    open.push(entity);
+                }
+            }
+        }
+
+        let Some(c) = c else { break };
+
+        while let Some(&entity) = entities.get(next).filter(|entity| entity.offset <= pos) {
+            next += 1;
+            if end(entity) > pos && !open.iter().any(|entity| is_code(entity)) {
+                writer.open(&mut out, entity);
+                open.push(entity);
+            }
+        }
+
+        let in_code = open.iter().any(|entity| is_code(entity));
+        writer.write_char(&mut out, c, in_code);
+        pos += c.len_utf16() as i64;
+    }
+
+    out
+}
+
+fn escape_html(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+}
+
+struct HtmlWriter;
+
+impl MarkupWriter for HtmlWriter {
+    fn open(&mut self, out: &mut String, entity: &MessageEntity) {
+        use MessageEntityType as T;
+
+        match entity.type_ {
+            T::Bold => out.push_str("<b>"),
+            T::Italic => out.push_str("<i>"),
+            T::Underline => out.push_str("<u>"),
+            T::Strikethrough => out.push_str("<s>"),
+            T::Spoiler => out.push_str("<tg-spoiler>"),
+            T::Blockquote => out.push_str("<blockquote>"),
+            T::ExpandableBlockquote => out.push_str("<blockquote expandable>"),
+            T::Code => out.push_str("<code>"),
+            T::Pre => match &entity.language {
+                Some(language) => {
+                    out.push_str("<pre><code class=\"language-");
+                    escape_html(out, language);
+                    out.push_str("\">");
+                }
+                None => out.push_str("<pre>"),
+            },
+            T::TextLink => {
+                out.push_str("<a href=\"");
+                escape_html(out, entity.url.as_deref().unwrap_or_default());
+                out.push_str("\">");
+            }
+            T::TextMention => {
+                out.push_str("<a href=\"");
+                out.push_str(&mention_url(entity.user.as_ref()));
+                out.push_str("\">");
+            }
+            T::CustomEmoji => {
+                out.push_str("<tg-emoji emoji-id=\"");
+                escape_html(out, entity.custom_emoji_id.as_deref().unwrap_or_default());
+                out.push_str("\">");
+            }
+            T::DateTime => {
+                out.push_str("<tg-time unix=\"");
+                out.push_str(&entity.unix_time.unwrap_or_default().to_string());
+                out.push('"');
+                if let Some(format) = &entity.date_time_format {
+                    out.push_str(" format=\"");
+                    escape_html(out, format);
+                    out.push('"');
+                }
+                out.push('>');
+            }
+            _ => {}
+        }
+    }
+
+    fn close(&mut self, out: &mut String, entity: &MessageEntity) {
+        use MessageEntityType as T;
+
+        out.push_str(match entity.type_ {
+            T::Bold => "</b>",
+            T::Italic => "</i>",
+            T::Underline => "</u>",
+            T::Strikethrough => "</s>",
+            T::Spoiler => "</tg-spoiler>",
+            T::Blockquote | T::ExpandableBlockquote => "</blockquote>",
+            T::Code => "</code>",
+            T::Pre if entity.language.is_some() => "</code></pre>",
+            T::Pre => "</pre>",
+            T::TextLink | T::TextMention => "</a>",
+            T::CustomEmoji => "</tg-emoji>",
+            T::DateTime => "</tg-time>",
+            _ => "",
+        });
+    }
+
+    fn write_char(&mut self, out: &mut String, c: char, _in_code: bool) {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct MarkdownV2Writer {
+    /// Last written markup ends with `_`, so a following `_` or `__` would be ambiguous
+    after_underscore: bool,
+
+    /// Blockquote is open and `>` must be written before the next line
+    pending_quote_marker: bool,
+    in_quote: bool,
+
+    /// Position in the output where the last blockquote ended
+    quote_ended_at: Option<usize>,
+}
+
+impl MarkdownV2Writer {
+    fn flush_quote_marker(&mut self, out: &mut String) {
+        if self.pending_quote_marker {
+            self.pending_quote_marker = false;
+            out.push('>');
+        }
+    }
+
+    fn marker(&mut self, out: &mut String, marker: &str) {
+        self.flush_quote_marker(out);
+
+        // "__" is greedily treated as underline, '\r' separates it from italic's '_'
+        if self.after_underscore && marker.starts_with('_') {
+            out.push('\r');
+        }
+        out.push_str(marker);
+        self.after_underscore = marker.ends_with('_');
+    }
+
+    fn open_quote(&mut self, out: &mut String) {
+        // Adjacent blockquotes must be separated by an empty bold entity
+        if self.quote_ended_at.is_some_and(|end| out.len() == end + 1) && out.ends_with('\n') {
+            out.push_str("**");
+        }
+        out.push('>');
+        self.after_underscore = false;
+        self.in_quote = true;
+    }
+
+    fn close_quote(&mut self, out: &mut String, expandable: bool) {
+        if expandable {
+            self.flush_quote_marker(out);
+            out.push_str("||");
+        }
+        self.after_underscore = false;
+        self.in_quote = false;
+        self.pending_quote_marker = false;
+        self.quote_ended_at = Some(out.len());
+    }
+
+    fn write_url(out: &mut String, url: &str) {
+        for c in url.chars() {
+            if matches!(c, ')' | '\\') {
+                out.push('\\');
+            }
+            out.push(c);
+        }
+    }
+}
+
+impl MarkupWriter for MarkdownV2Writer {
+    fn open(&mut self, out: &mut String, entity: &MessageEntity) {
+        use MessageEntityType as T;
+
+        match entity.type_ {
+            T::Bold => self.marker(out, "*"),
+            T::Italic => self.marker(out, "_"),
+            T::Underline => self.marker(out, "__"),
+            T::Strikethrough => self.marker(out, "~"),
+            T::Spoiler => self.marker(out, "||"),
+            T::Blockquote | T::ExpandableBlockquote => self.open_quote(out),
+            T::Code => self.marker(out, "`"),
+            T::Pre => {
+                self.marker(out, "```");
+                out.push_str(entity.language.as_deref().unwrap_or_default());
+                out.push('\n');
+            }
+            T::TextLink | T::TextMention => self.marker(out, "["),
+            T::CustomEmoji | T::DateTime => self.marker(out, "!["),
+            _ => {}
+        }
+    }
+
+    fn close(&mut self, out: &mut String, entity: &MessageEntity) {
+        use MessageEntityType as T;
+
+        match entity.type_ {
+            T::Bold => self.marker(out, "*"),
+            T::Italic => self.marker(out, "_"),
+            T::Underline => self.marker(out, "__"),
+            T::Strikethrough => self.marker(out, "~"),
+            T::Spoiler => self.marker(out, "||"),
+            T::Blockquote => self.close_quote(out, false),
+            T::ExpandableBlockquote => self.close_quote(out, true),
+            T::Code => self.marker(out, "`"),
+            T::Pre => self.marker(out, "```"),
+            T::TextLink => {
+                self.marker(out, "](");
+                Self::write_url(out, entity.url.as_deref().unwrap_or_default());
+                out.push(')');
+            }
+            T::TextMention => {
+                self.marker(out, "](");
+                Self::write_url(out, &mention_url(entity.user.as_ref()));
+                out.push(')');
+            }
+            T::CustomEmoji => {
+                self.marker(out, "](tg://emoji?id=");
+                Self::write_url(out, entity.custom_emoji_id.as_deref().unwrap_or_default());
+                out.push(')');
+            }
+            T::DateTime => {
+                self.marker(out, "](tg://time?unix=");
+                out.push_str(&entity.unix_time.unwrap_or_default().to_string());
+                if let Some(format) = &entity.date_time_format {
+                    out.push_str("&format=");
+                    Self::write_url(out, format);
+                }
+                out.push(')');
+            }
+            _ => {}
+        }
+    }
+
+    fn write_char(&mut self, out: &mut String, c: char, in_code: bool) {
+        self.flush_quote_marker(out);
+        self.after_underscore = false;
+
+        let escape = if in_code {
+            matches!(c, '`' | '\\')
+        } else {
+            c == '\\' || c == '\r' || MARKDOWN_V2_RESERVED.contains(c)
+        };
+        if escape {
+            out.push('\\');
+        }
+        out.push(c);
+
+        if c == '\n' && self.in_quote {
+            self.pending_quote_marker = true;
+        }
+    }
+}
+
+/// Text and entities being parsed
+#[derive(Debug, Default)]
+struct TextBuilder {
+    text: String,
+    len_utf16: usize,
+    entities: Vec<MessageEntity>,
+}
+
+impl TextBuilder {
+    fn push(&mut self, c: char) {
+        self.text.push(c);
+        self.len_utf16 += c.len_utf16();
+    }
+
+    /// Entity from `start` to the current position, empty ones are skipped as Telegram does
+    fn add_entity(&mut self, entity: MessageEntity, start: usize) {
+        self.add_entity_range(entity, start, self.len_utf16);
+    }
+
+    fn add_entity_range(&mut self, mut entity: MessageEntity, start: usize, end: usize) {
+        if end > start {
+            entity.offset = start as i64;
+            entity.length = (end - start) as i64;
+            self.entities.push(entity);
+        }
+    }
+
+    fn finish(mut self) -> FormattedText {
+        // Entities are added as they are closed, so the outer of entities with the same range goes first after reversing
+        self.entities.reverse();
+        self.entities
+            .sort_by_key(|entity| (entity.offset, Reverse(entity.length)));
+        FormattedText::with_text(self.text, self.entities)
+    }
+}
+
+/// Decodes `&lt;`, `&gt;`, `&amp;`, `&quot;` and numeric character references at the start of `text`,
+/// returns the char and the reference length
+fn decode_html_entity(text: &str) -> Option<(char, usize)> {
+    let end = text.get(..12).unwrap_or(text).find(';')?;
+    let name = &text[1..end];
+
+    let c = match name {
+        "lt" => '<',
+        "gt" => '>',
+        "amp" => '&',
+        "quot" => '"',
+        _ => {
+            let code = name.strip_prefix('#')?;
+            let code = match code.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => code.parse().ok()?,
+            };
+            char::from_u32(code)?
+        }
+    };
+
+    Some((c, end + 1))
+}
+
+fn decode_html(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        let (c, len) = if c == '&' {
+            decode_html_entity(rest).unwrap_or((c, 1))
+        } else {
+            (c, c.len_utf8())
+        };
+        decoded.push(c);
+        rest = &rest[len..];
+    }
+
+    decoded
+}
+
+#[derive(Debug)]
+struct HtmlTag {
+    name: String,
+    attributes: Vec<(String, String)>,
+    is_end: bool,
+
+    /// Length of the tag in bytes, including `<` and `>`
+    len: usize,
+}
+
+impl HtmlTag {
+    /// Parse the tag at the start of `text`, `offset` is used for errors
+    fn parse(text: &str, offset: usize) -> Result<Self, ParseMarkupError> {
+        let unclosed = || ParseMarkupError::Unclosed {
+            entity: "tag".to_owned(),
+            offset,
+        };
+        let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '-';
+
+        let mut rest = &text[1..];
+        let is_end = rest.starts_with('/');
+        if is_end {
+            rest = &rest[1..];
+        }
+
+        let name_len = rest.find(|c| !is_name_char(c)).ok_or_else(unclosed)?;
+        let name = rest[..name_len].to_ascii_lowercase();
+        if name.is_empty() {
+            return Err(ParseMarkupError::Invalid {
+                what: "tag name",
+                offset,
+            });
+        }
+        rest = &rest[name_len..];
+
+        let mut attributes = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix('>') {
+                return Ok(Self {
+                    name,
+                    attributes,
+                    is_end,
+                    len: text.len() - after.len(),
+                });
+            }
+
+            let attribute_len = rest.find(|c| !is_name_char(c)).ok_or_else(unclosed)?;
+            if attribute_len == 0 || is_end {
+                return Err(ParseMarkupError::Invalid {
+                    what: "tag attribute",
+                    offset,
+                });
+            }
+            let attribute = rest[..attribute_len].to_ascii_lowercase();
+            rest = rest[attribute_len..].trim_start();
+
+            let Some(after) = rest.strip_prefix('=') else {
+                attributes.push((attribute, String::new()));
+                continue;
+            };
+            rest = after.trim_start();
+
+            let value = match rest.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let value_len = rest[1..].find(quote).ok_or_else(unclosed)?;
+                    let value = &rest[1..=value_len];
+                    rest = &rest[value_len + 2..];
+                    value
+                }
+                Some(_) => {
+                    let value_len = rest
+                        .find(|c: char| c.is_whitespace() || c == '>')
+                        .ok_or_else(unclosed)?;
+                    let value = &rest[..value_len];
+                    rest = &rest[value_len..];
+                    value
+                }
+                None => return Err(unclosed()),
+            };
+            attributes.push((attribute, decode_html(value)));
+        }
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| attribute == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug)]
+struct OpenHtmlTag {
+    name: String,
+    entity: Option<MessageEntity>,
+
+    /// Utf-16 offset of the tag content
+    start: usize,
+
+    /// Byte offset of the tag in the markup
+    offset: usize,
+}
+
+#[derive(Debug, Default)]
+struct HtmlParser {
+    out: TextBuilder,
+    open: Vec<OpenHtmlTag>,
+}
+
+impl HtmlParser {
+    fn parse(mut self, html: &str) -> Result<FormattedText, ParseMarkupError> {
+        let mut offset = 0;
+
+        while let Some(c) = html[offset..].chars().next() {
+            match c {
+                '<' => {
+                    let tag = HtmlTag::parse(&html[offset..], offset)?;
+                    if tag.is_end {
+                        self.close_tag(&tag.name, offset)?;
+                    } else {
+                        self.open_tag(&tag, offset)?;
+                    }
+                    offset += tag.len;
+                }
+                '&' => {
+                    let (c, len) = decode_html_entity(&html[offset..]).unwrap_or((c, 1));
+                    self.out.push(c);
+                    offset += len;
+                }
+                c => {
+                    self.out.push(c);
+                    offset += c.len_utf8();
+                }
+            }
+        }
+
+        if let Some(tag) = self.open.last() {
+            return Err(ParseMarkupError::Unclosed {
+                entity: format!("<{}>", tag.name),
+                offset: tag.offset,
+            });
+        }
+
+        Ok(self.out.finish())
+    }
+
+    fn open_tag(&mut self, tag: &HtmlTag, offset: usize) -> Result<(), ParseMarkupError> {
+        use MessageEntityType as T;
+
+        let simple = |type_| {
+            Some(MessageEntity {
+                type_,
+                ..Default::default()
+            })
+        };
+
+        let entity = match tag.name.as_str() {
+            "b" | "strong" => simple(T::Bold),
+            "i" | "em" => simple(T::Italic),
+            "u" | "ins" => simple(T::Underline),
+            "s" | "strike" | "del" => simple(T::Strikethrough),
+            "tg-spoiler" => simple(T::Spoiler),
+            "span" if tag.attribute("class") == Some("tg-spoiler") => simple(T::Spoiler),
+            "blockquote" if tag.attribute("expandable").is_some() => {
+                simple(T::ExpandableBlockquote)
+            }
+            "blockquote" => simple(T::Blockquote),
+            "pre" => simple(T::Pre),
+            "code" => match self.open.last_mut() {
+                // <pre><code class="language-..."> sets the language of the code block
+                Some(OpenHtmlTag {
+                    entity: Some(pre),
+                    start,
+                    ..
+                }) if pre.type_ == T::Pre => {
+                    if *start == self.out.len_utf16
+                        && let Some(language) = tag
+                            .attribute("class")
+                            .and_then(|class| class.strip_prefix("language-"))
+                    {
+                        pre.language = Some(language.to_owned());
+                    }
+                    None
+                }
+                _ => simple(T::Code),
+            },
+            "a" => link_entity(tag.attribute("href").unwrap_or_default().to_owned()),
+            "tg-emoji" => {
+                let Some(id) = tag
+                    .attribute("emoji-id")
+                    .filter(|id| !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()))
+                else {
+                    return Err(ParseMarkupError::Invalid {
+                        what: "custom emoji id",
+                        offset,
+                    });
+                };
+                Some(MessageEntity {
+                    type_: T::CustomEmoji,
+                    custom_emoji_id: Some(id.to_owned()),
+                    ..Default::default()
+                })
+            }
+            "tg-time" => {
+                let Some(unix_time) = tag.attribute("unix").and_then(|unix| unix.parse().ok())
+                else {
+                    return Err(ParseMarkupError::Invalid {
+                        what: "unix time",
+                        offset,
+                    });
+                };
+                Some(MessageEntity {
+                    type_: T::DateTime,
+                    unix_time: Some(unix_time),
+                    date_time_format: tag.attribute("format").map(ToOwned::to_owned),
+                    ..Default::default()
+                })
+            }
+            _ => {
+                return Err(ParseMarkupError::UnsupportedTag {
+                    tag: tag.name.clone(),
+                    offset,
+                });
+            }
+        };
+
+        self.open.push(OpenHtmlTag {
+            name: tag.name.clone(),
+            entity,
+            start: self.out.len_utf16,
+            offset,
+        });
+        Ok(())
+    }
+
+    fn close_tag(&mut self, name: &str, offset: usize) -> Result<(), ParseMarkupError> {
+        match self.open.pop() {
+            Some(tag) if tag.name == name => {
+                if let Some(entity) = tag.entity {
+                    self.out.add_entity(entity, tag.start);
+                }
+                Ok(())
+            }
+            _ => Err(ParseMarkupError::UnexpectedEndTag {
+                tag: name.to_owned(),
+                offset,
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MarkdownV2Marker {
+    Bold,
+    Italic,
+    Underline,
+    Strikethrough,
+    Spoiler,
+    /// `[text](url)`
+    Link,
+    /// `![emoji](tg://emoji?id=...)` or `![time](tg://time?unix=...)`
+    Emoji,
+}
+
+impl MarkdownV2Marker {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Bold => "Bold entity",
+            Self::Italic => "Italic entity",
+            Self::Underline => "Underline entity",
+            Self::Strikethrough => "Strikethrough entity",
+            Self::Spoiler => "Spoiler entity",
+            Self::Link => "TextUrl entity",
+            Self::Emoji => "CustomEmoji entity",
+        }
+    }
+
+    const fn entity_type(self) -> MessageEntityType {
+        match self {
+            Self::Bold => MessageEntityType::Bold,
+            Self::Italic => MessageEntityType::Italic,
+            Self::Underline => MessageEntityType::Underline,
+            Self::Strikethrough => MessageEntityType::Strikethrough,
+            Self::Spoiler => MessageEntityType::Spoiler,
+            Self::Link => MessageEntityType::TextLink,
+            Self::Emoji => MessageEntityType::CustomEmoji,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct OpenMarker {
+    marker: MarkdownV2Marker,
+    start: usize,
+    offset: usize,
+}
+
+#[derive(Debug)]
+struct OpenQuote {
+    start: usize,
+
+    /// The last line ended with `||`, so the blockquote is expandable and ends on this line
+    expandable: bool,
+}
+
+#[derive(Debug, Default)]
+struct MarkdownV2Parser {
+    out: TextBuilder,
+    open: Vec<OpenMarker>,
+    quote: Option<OpenQuote>,
+}
+
+/// Escaped char at the start of `text` (after `\`), any ASCII char except NUL can be escaped
+fn escaped_char(text: &str) -> Option<char> {
+    text.chars().next().filter(|c| matches!(c, '\x01'..='\x7e'))
+}
+
+impl MarkdownV2Parser {
+    fn parse(mut self, markdown: &str) -> Result<FormattedText, ParseMarkupError> {
+        let mut offset = 0;
+        let mut line_start = true;
+
+        loop {
+            if line_start {
+                line_start = false;
+                offset += self.start_line(&markdown[offset..]);
+            }
+
+            let rest = &markdown[offset..];
+            let Some(c) = rest.chars().next() else { break };
+
+            offset += match c {
+                '\\' => {
+                    if let Some(escaped) = escaped_char(&rest[1..]) {
+                        self.out.push(escaped);
+                        2
+                    } else {
+                        self.out.push(c);
+                        1
+                    }
+                }
+                '\r' => 1,
+                '\n' => {
+                    self.out.push(c);
+                    line_start = true;
+                    1
+                }
+                '*' => self.toggle(MarkdownV2Marker::Bold, offset, 1),
+                '_' if rest.starts_with("__") => {
+                    self.toggle(MarkdownV2Marker::Underline, offset, 2)
+                }
+                '_' => self.toggle(MarkdownV2Marker::Italic, offset, 1),
+                '~' => self.toggle(MarkdownV2Marker::Strikethrough, offset, 1),
+                '|' if rest.starts_with("||") => {
+                    let ends_line = matches!(rest[2..].chars().next(), None | Some('\n'));
+                    let closes_spoiler = self
+                        .open
+                        .last()
+                        .is_some_and(|open| open.marker == MarkdownV2Marker::Spoiler);
+
+                    match &mut self.quote {
+                        Some(quote) if ends_line && !closes_spoiler => {
+                            quote.expandable = true;
+                            2
+                        }
+                        _ => self.toggle(MarkdownV2Marker::Spoiler, offset, 2),
+                    }
+                }
+                '`' if rest.starts_with("```") => self.parse_pre(rest, offset)?,
+                '`' => self.parse_code(rest, offset)?,
+                '[' => self.push_marker(MarkdownV2Marker::Link, offset, 1),
+                '!' if rest.starts_with("![") => {
+                    self.push_marker(MarkdownV2Marker::Emoji, offset, 2)
+                }
+                ']' if self.open.last().is_some_and(|open| {
+                    matches!(
+                        open.marker,
+                        MarkdownV2Marker::Link | MarkdownV2Marker::Emoji
+                    )
+                }) =>
+                {
+                    self.parse_url(rest, offset)?
+                }
+                c if MARKDOWN_V2_RESERVED.contains(c) => {
+                    return Err(ParseMarkupError::ReservedCharacter {
+                        character: c,
+                        offset,
+                    });
+                }
+                c => {
+                    self.out.push(c);
+                    c.len_utf8()
+                }
+            };
+        }
+
+        if let Some(open) = self.open.last() {
+            return Err(ParseMarkupError::Unclosed {
+                entity: open.marker.name().to_owned(),
+                offset: open.offset,
+            });
+        }
+        self.close_quote(self.out.len_utf16);
+
+        Ok(self.out.finish())
+    }
+
+    /// Handles blockquote markers at the start of a line, returns their length
+    fn start_line(&mut self, line: &str) -> usize {
+        // Empty bold entity separates adjacent blockquotes
+        let (marker_len, separated) = if line.starts_with("**>") {
+            (3, true)
+        } else if line.starts_with('>') {
+            (1, false)
+        } else {
+            (0, false)
+        };
+
+        let continues_quote = marker_len > 0
+            && !separated
+            && self.quote.as_ref().is_some_and(|quote| !quote.expandable);
+        if !continues_quote {
+            // The previous line's '\n' is not a part of the blockquote
+            self.close_quote(self.out.len_utf16.saturating_sub(1));
+        }
+
+        if marker_len > 0 && self.quote.is_none() {
+            self.quote = Some(OpenQuote {
+                start: self.out.len_utf16,
+                expandable: false,
+            });
+        }
+
+        marker_len
+    }
+
+    fn close_quote(&mut self, end: usize) {
+        if let Some(quote) = self.quote.take() {
+            let type_ = if quote.expandable {
+                MessageEntityType::ExpandableBlockquote
+            } else {
+                MessageEntityType::Blockquote
+            };
+            self.out.add_entity_range(
+                MessageEntity {
+                    type_,
+                    ..Default::default()
+                },
+                quote.start,
+                end,
+            );
+        }
+    }
+
+    fn push_marker(&mut self, marker: MarkdownV2Marker, offset: usize, len: usize) -> usize {
+        self.open.push(OpenMarker {
+            marker,
+            start: self.out.len_utf16,
+            offset,
+        });
+        len
+    }
+
+    /// Closes the innermost entity if it has the same marker, opens a new one otherwise
+    fn toggle(&mut self, marker: MarkdownV2Marker, offset: usize, len: usize) -> usize {
+        match self.open.pop() {
+            Some(open) if open.marker == marker => {
+                self.out.add_entity(
+                    MessageEntity {
+                        type_: marker.entity_type(),
+                        ..Default::default()
+                    },
+                    open.start,
+                );
+                len
+            }
+            other => {
+                self.open.extend(other);
+                self.push_marker(marker, offset, len)
+            }
+        }
+    }
+
+    /// Reads code until `end`, where only escapes are processed. Returns the code and its length with `end`
+    fn read_code(
+        text: &str,
+        end: &str,
+        entity: &str,
+        offset: usize,
+    ) -> Result<(String, usize), ParseMarkupError> {
+        let mut code = String::new();
+        let mut pos = 0;
+
+        while let Some(c) = text[pos..].chars().next() {
+            if text[pos..].starts_with(end) {
+                return Ok((code, pos + end.len()));
+            }
+
+            if c == '\\'
+                && let Some(escaped) = escaped_char(&text[pos + 1..])
+            {
+                code.push(escaped);
+                pos += 2;
+            } else {
+                code.push(c);
+                pos += c.len_utf8();
+            }
+        }
+
+        Err(ParseMarkupError::Unclosed {
+            entity: entity.to_owned(),
+            offset,
+        })
+    }
+
+    fn parse_code(&mut self, text: &str, offset: usize) -> Result<usize, ParseMarkupError> {
+        let (code, len) = Self::read_code(&text[1..], "`", "Code entity", offset)?;
+
+        let start = self.out.len_utf16;
+        code.chars().for_each(|c| self.out.push(c));
+        self.out.add_entity(
+            MessageEntity {
+                type_: MessageEntityType::Code,
+                ..Default::default()
+            },
+            start,
+        );
+
+        Ok(len + 1)
+    }
+
+    fn parse_pre(&mut self, text: &str, offset: usize) -> Result<usize, ParseMarkupError> {
+        let (code, len) = Self::read_code(&text[3..], "```", "Pre entity", offset)?;
+
+        // The first line is the language, unless it looks like code
+        let (language, code) = match code.split_once('\n') {
+            Some((language, code)) if !language.contains(char::is_whitespace) => {
+                (Some(language).filter(|language| !language.is_empty()), code)
+            }
+            _ => (None, code.as_str()),
+        };
+
+        let start = self.out.len_utf16;
+        code.chars().for_each(|c| self.out.push(c));
+        self.out.add_entity(
+            MessageEntity {
+                type_: MessageEntityType::Pre,
+                language: language.map(ToOwned::to_owned),
+                ..Default::default()
+            },
+            start,
+        );
+
+        Ok(len + 3)
+    }
+
+    /// Parses `](url)` closing a link or a custom emoji
+    fn parse_url(&mut self, text: &str, offset: usize) -> Result<usize, ParseMarkupError> {
+        let open = self.open.pop().expect("Link marker was just checked");
+
+        let Some(rest) = text[1..].strip_prefix('(') else {
+            // Link without URL is a plain text
+            return Ok(1);
+        };
+
+        let mut url = String::new();
+        let mut pos = 0;
+        loop {
+            let Some(c) = rest[pos..].chars().next() else {
+                return Err(ParseMarkupError::Unclosed {
+                    entity: "URL".to_owned(),
+                    offset,
+                });
+            };
+
+            if c == ')' {
+                break;
+            }
+
+            if c == '\\'
+                && let Some(escaped) = escaped_char(&rest[pos + 1..])
+            {
+                url.push(escaped);
+                pos += 2;
+            } else {
+                url.push(c);
+                pos += c.len_utf8();
+            }
+        }
+
+        let entity = match open.marker {
+            MarkdownV2Marker::Link => link_entity(url),
+            _ => Some(Self::emoji_entity(&url).ok_or(ParseMarkupError::Invalid {
+                what: "custom emoji or time URL",
+                offset,
+            })?),
+        };
+        if let Some(entity) = entity {
+            self.out.add_entity(entity, open.start);
+        }
+
+        // "](" + url + ")"
+        Ok(2 + pos + 1)
+    }
+
+    /// Entity for `tg://emoji?id=...` or `tg://time?unix=...&format=...`
+    fn emoji_entity(url: &str) -> Option<MessageEntity> {
+        let (kind, query) = url.strip_prefix("tg://")?.split_once('?')?;
+        let param = |name: &str| {
+            query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+        };
+
+        match kind {
+            "emoji" => {
+                let id = param("id")
+                    .filter(|id| !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()))?;
+                Some(MessageEntity {
+                    type_: MessageEntityType::CustomEmoji,
+                    custom_emoji_id: Some(id.to_owned()),
+                    ..Default::default()
+                })
+            }
+            "time" => Some(MessageEntity {
+                type_: MessageEntityType::DateTime,
+                unix_time: Some(param("unix")?.parse().ok()?),
+                date_time_format: param("format").map(ToOwned::to_owned),
+                ..Default::default()
+            }),
+            _ => None,
+        }
+    }
+}
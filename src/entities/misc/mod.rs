@@ -1,6 +1,8 @@
 pub mod chat_id;
 pub mod formatting;
 pub mod input_file;
+pub mod markup;
 pub mod message_effects;
 pub mod reply_builder;
 pub mod reply_markup;
+pub mod split;
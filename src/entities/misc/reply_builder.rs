@@ -4,13 +4,15 @@ use crate::{
     api::Api,
     entities::{
         message::{InputMessageText, Message},
-        misc::chat_id::ChatId,
+        misc::{chat_id::ChatId, formatting::FormattedText},
         reply_parameters::ReplyParameters,
     },
+    errors::ConogramError,
     methods::send_message::SendMessageRequest,
+    request::RequestT,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ReplyBuilder<'a> {
     pub(crate) api: &'a Api,
     pub(crate) reply_parameters: ReplyParameters,
@@ -114,4 +116,23 @@ impl<'a> ReplyBuilder<'a> {
     pub fn text(self, text: impl Into<InputMessageText>) -> SendMessageRequest<'a> {
         self.message(text)
     }
+
+    /// Send a text longer than [`MAX_MESSAGE_LEN`](super::split::MAX_MESSAGE_LEN) as several messages,
+    /// the first one replies to this message and each next one to the previous
+    ///
+    /// Messages are sent with [`RequestT::wrap`], sending stops at the first error
+    pub async fn text_split(
+        mut self,
+        text: impl Into<FormattedText>,
+    ) -> Result<Vec<Message>, ConogramError> {
+        let mut sent = Vec::new();
+
+        for part in text.into().split_message() {
+            let message = Box::pin(self.clone().text(part).wrap()).await?;
+            self.reply_parameters = ReplyParameters::reply(&message);
+            sent.push(message);
+        }
+
+        Ok(sent)
+    }
 }
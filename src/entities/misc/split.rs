@@ -0,0 +1,151 @@
+//! Splitting of long [`FormattedText`] into parts which fit into a single message
+
+use super::formatting::{FormattedText, Utf16Len};
+use crate::entities::message_entity::MessageEntityType;
+
+/// Max length of a message text in utf-16 codeunits
+pub const MAX_MESSAGE_LEN: usize = 4096;
+
+/// Max length of a media caption in utf-16 codeunits
+pub const MAX_CAPTION_LEN: usize = 1024;
+
+/// Where a text can be cut, from the most to the least preferable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Boundary {
+    Word,
+    Line,
+    Paragraph,
+}
+
+impl FormattedText {
+    /// Split into parts of at most `max_len` utf-16 codeunits, cutting at paragraph, line or word boundaries when possible
+    ///
+    /// Entities crossing a cut are continued in the next part, whitespace around cuts is dropped as Telegram would trim it
+    #[must_use]
+    pub fn split(&self, max_len: usize) -> Vec<Self> {
+        self.split_with(max_len, max_len)
+    }
+
+    /// Split into parts which fit into a text message, see [`MAX_MESSAGE_LEN`]
+    #[must_use]
+    pub fn split_message(&self) -> Vec<Self> {
+        self.split(MAX_MESSAGE_LEN)
+    }
+
+    /// Split into a media caption (see [`MAX_CAPTION_LEN`]) and text messages for the rest
+    #[must_use]
+    pub fn split_caption(&self) -> (Self, Vec<Self>) {
+        let mut parts = self
+            .split_with(MAX_CAPTION_LEN, MAX_MESSAGE_LEN)
+            .into_iter();
+        (parts.next().unwrap_or_default(), parts.collect())
+    }
+
+    fn split_with(&self, first_max_len: usize, max_len: usize) -> Vec<Self> {
+        let text = self.get_text();
+        let chars = text
+            .chars()
+            .scan(0, |pos, c| {
+                let start = *pos;
+                *pos += c.len_utf16();
+                Some((start, c))
+            })
+            .collect::<Vec<_>>();
+
+        let mut parts = Vec::new();
+        let mut i = 0;
+
+        loop {
+            while chars.get(i).is_some_and(|(_, c)| c.is_whitespace()) {
+                i += 1;
+            }
+            let Some(&(start, _)) = chars.get(i) else {
+                break;
+            };
+
+            let max_len = if parts.is_empty() {
+                first_max_len
+            } else {
+                max_len
+            }
+            .max(2);
+
+            // Index of the first char which doesn't fit
+            let Some(end) = chars[i..]
+                .iter()
+                .position(|(pos, c)| pos + c.len_utf16() > start + max_len)
+                .map(|end| i + end)
+            else {
+                parts.push(self.slice(start..self.len_utf16()).trim_end());
+                break;
+            };
+
+            let cut = Self::find_boundary(&chars, i, end, max_len)
+                .unwrap_or_else(|| self.hard_cut(&chars, i, end));
+
+            parts.push(self.slice(start..chars[cut].0).trim_end());
+            i = cut;
+        }
+
+        parts
+    }
+
+    /// Index of the whitespace char to cut at, paragraph and line breaks are used only if the part is at least half full
+    fn find_boundary(
+        chars: &[(usize, char)],
+        start: usize,
+        end: usize,
+        max_len: usize,
+    ) -> Option<usize> {
+        let start_pos = chars[start].0;
+
+        (start + 1..=end)
+            .filter_map(|k| {
+                let (pos, c) = chars[k];
+                let boundary = match c {
+                    '\n' if chars.get(k + 1).is_some_and(|(_, next)| *next == '\n') => {
+                        Boundary::Paragraph
+                    }
+                    '\n' => Boundary::Line,
+                    c if c.is_whitespace() => Boundary::Word,
+                    _ => return None,
+                };
+
+                let boundary = if boundary > Boundary::Word && pos - start_pos < max_len / 2 {
+                    Boundary::Word
+                } else {
+                    boundary
+                };
+                Some((boundary, k))
+            })
+            .max()
+            .map(|(_, k)| k)
+    }
+
+    /// Index to cut at when there is no whitespace, custom emoji are not cut in half
+    fn hard_cut(&self, chars: &[(usize, char)], start: usize, end: usize) -> usize {
+        let cut_pos = chars[end].0;
+
+        self.get_entities()
+            .iter()
+            .filter(|ent| {
+                ent.type_ == MessageEntityType::CustomEmoji
+                    && ent.offset < cut_pos as i64
+                    && ent.offset + ent.length > cut_pos as i64
+            })
+            .find_map(|ent| {
+                chars[start + 1..end]
+                    .iter()
+                    .position(|(pos, _)| *pos as i64 == ent.offset)
+                    .map(|k| start + 1 + k)
+            })
+            .unwrap_or(end)
+    }
+
+    /// Without trailing whitespace, which Telegram would trim
+    fn trim_end(&self) -> Self {
+        let text = self.get_text();
+        let trimmed = text.trim_end().len();
+        self.slice(0..self.len_utf16() - (&text[trimmed..]).utf16_codeunits())
+    }
+}
@@ -130,7 +130,11 @@ pub struct Update {
 // Divider: all content below this line will be preserved after code regen
 use std::fmt::Display;
 
-#[derive(Debug, Clone, Copy)]
+use crate::entities::{
+    chat::Chat, maybe_inaccessible_message::MaybeInaccessibleMessage, user::User,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum AllowedUpdates {
     Message,
     EditedMessage,
@@ -252,3 +256,157 @@ impl From<AllowedUpdates> for String {
         value.as_str().into()
     }
 }
+
+impl Update {
+    /// Kind of the update, `None` if the update has no known content
+    #[must_use]
+    pub const fn kind(&self) -> Option<AllowedUpdates> {
+        let kind = if self.message.is_some() {
+            AllowedUpdates::Message
+        } else if self.edited_message.is_some() {
+            AllowedUpdates::EditedMessage
+        } else if self.channel_post.is_some() {
+            AllowedUpdates::ChannelPost
+        } else if self.edited_channel_post.is_some() {
+            AllowedUpdates::EditedChannelPost
+        } else if self.business_connection.is_some() {
+            AllowedUpdates::BusinessConnection
+        } else if self.business_message.is_some() {
+            AllowedUpdates::BusinessMessage
+        } else if self.edited_business_message.is_some() {
+            AllowedUpdates::EditedBusinessMessage
+        } else if self.deleted_business_messages.is_some() {
+            AllowedUpdates::DeletedBusinessMessages
+        } else if self.guest_message.is_some() {
+            AllowedUpdates::GuestMessage
+        } else if self.message_reaction.is_some() {
+            AllowedUpdates::MessageReaction
+        } else if self.message_reaction_count.is_some() {
+            AllowedUpdates::MessageReactionCount
+        } else if self.inline_query.is_some() {
+            AllowedUpdates::InlineQuery
+        } else if self.chosen_inline_result.is_some() {
+            AllowedUpdates::ChosenInlineResult
+        } else if self.callback_query.is_some() {
+            AllowedUpdates::CallbackQuery
+        } else if self.shipping_query.is_some() {
+            AllowedUpdates::ShippingQuery
+        } else if self.pre_checkout_query.is_some() {
+            AllowedUpdates::PreCheckoutQuery
+        } else if self.purchased_paid_media.is_some() {
+            AllowedUpdates::PurchasedPaidMedia
+        } else if self.poll.is_some() {
+            AllowedUpdates::Poll
+        } else if self.poll_answer.is_some() {
+            AllowedUpdates::PollAnswer
+        } else if self.my_chat_member.is_some() {
+            AllowedUpdates::MyChatMember
+        } else if self.chat_member.is_some() {
+            AllowedUpdates::ChatMember
+        } else if self.chat_join_request.is_some() {
+            AllowedUpdates::ChatJoinRequest
+        } else if self.chat_boost.is_some() {
+            AllowedUpdates::ChatBoost
+        } else if self.removed_chat_boost.is_some() {
+            AllowedUpdates::RemovedChatBoost
+        } else if self.managed_bot.is_some() {
+            AllowedUpdates::ManagedBot
+        } else if self.subscription.is_some() {
+            AllowedUpdates::BotSubscriptionUpdated
+        } else {
+            return None;
+        };
+
+        Some(kind)
+    }
+
+    /// Message of any kind (new, edited, channel post, business or guest message) or the message the callback query originated from
+    #[must_use]
+    pub fn effective_message(&self) -> Option<&Message> {
+        if let Some(message) = self
+            .message
+            .as_ref()
+            .or(self.edited_message.as_ref())
+            .or(self.channel_post.as_ref())
+            .or(self.edited_channel_post.as_ref())
+            .or(self.business_message.as_ref())
+            .or(self.edited_business_message.as_ref())
+            .or(self.guest_message.as_ref())
+        {
+            Some(message)
+        } else {
+            self.callback_query
+                .as_ref()
+                .and_then(CallbackQuery::message)
+        }
+    }
+
+    /// Chat the update belongs to, if any
+    #[must_use]
+    pub fn effective_chat(&self) -> Option<&Chat> {
+        if let Some(message) = self.effective_message() {
+            Some(&message.chat)
+        } else if let Some(callback_query) = &self.callback_query {
+            callback_query.message.as_deref().map(|m| match m {
+                MaybeInaccessibleMessage::Message(m) => &*m.chat,
+                MaybeInaccessibleMessage::InaccessibleMessage(m) => &*m.chat,
+            })
+        } else if let Some(v) = &self.my_chat_member {
+            Some(&v.chat)
+        } else if let Some(v) = &self.chat_member {
+            Some(&v.chat)
+        } else if let Some(v) = &self.chat_join_request {
+            Some(&v.chat)
+        } else if let Some(v) = &self.message_reaction {
+            Some(&v.chat)
+        } else if let Some(v) = &self.message_reaction_count {
+            Some(&v.chat)
+        } else if let Some(v) = &self.deleted_business_messages {
+            Some(&v.chat)
+        } else if let Some(v) = &self.chat_boost {
+            Some(&v.chat)
+        } else if let Some(v) = &self.removed_chat_boost {
+            Some(&v.chat)
+        } else {
+            self.poll_answer
+                .as_ref()
+                .and_then(|v| v.voter_chat.as_deref())
+        }
+    }
+
+    /// User that caused the update, if any
+    #[must_use]
+    pub fn effective_user(&self) -> Option<&User> {
+        if let Some(v) = &self.callback_query {
+            Some(&v.from)
+        } else if let Some(message) = self.effective_message() {
+            message.from.as_ref()
+        } else if let Some(v) = &self.inline_query {
+            Some(&v.from)
+        } else if let Some(v) = &self.chosen_inline_result {
+            Some(&v.from)
+        } else if let Some(v) = &self.shipping_query {
+            Some(&v.from)
+        } else if let Some(v) = &self.pre_checkout_query {
+            Some(&v.from)
+        } else if let Some(v) = &self.purchased_paid_media {
+            Some(&v.from)
+        } else if let Some(v) = &self.my_chat_member {
+            Some(&v.from)
+        } else if let Some(v) = &self.chat_member {
+            Some(&v.from)
+        } else if let Some(v) = &self.chat_join_request {
+            Some(&v.from)
+        } else if let Some(v) = &self.business_connection {
+            Some(&v.user)
+        } else if let Some(v) = &self.managed_bot {
+            Some(&v.user)
+        } else if let Some(v) = &self.subscription {
+            Some(&v.user)
+        } else if let Some(v) = &self.message_reaction {
+            v.user.as_ref()
+        } else {
+            self.poll_answer.as_ref().and_then(|v| v.user.as_ref())
+        }
+    }
+}
@@ -6,6 +6,9 @@ use thiserror::Error;
 
 use crate::client::TgApiResponse;
 
+/// Failed API call
+///
+/// Bot tokens are [redacted](crate::redact) from the params and the error, so it's safe to log
 #[derive(Error)]
 pub struct ConogramError {
     pub method_name: String,
@@ -32,6 +35,15 @@ impl Display for ConogramError {
 }
 
 impl ConogramError {
+    /// Kind of the error returned by the Bot API, `None` for other errors (network, serialization etc.)
+    #[must_use]
+    pub fn api_error_kind(&self) -> Option<ApiErrorKind> {
+        match &self.type_ {
+            ConogramErrorType::ApiError(error) => Some(error.kind()),
+            _ => None,
+        }
+    }
+
     pub(crate) fn new(
         method_name: impl Into<String>,
         params: impl serde::Serialize + std::fmt::Debug,
@@ -42,6 +54,7 @@ impl ConogramError {
             params: serde_json::to_value(params).unwrap(),
             type_: error,
         }
+        .redact(None)
     }
 }
 
@@ -62,6 +75,37 @@ pub enum ConogramErrorType {
     /// IO errors
     #[error("{0}")]
     IO(#[from] std::io::Error),
+
+    /// File exceeds the download limit, `size` is the number of bytes known so far
+    #[error("File is too big to download: {size} bytes, at most {max_size} bytes are allowed")]
+    FileTooBig { size: u64, max_size: u64 },
+
+    /// Errors returned by a custom [Transport](crate::transport::Transport)
+    #[error("{0}")]
+    TransportError(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl ConogramErrorType {
+    /// Short name of the error, e.g. `retry_after` or `request`, used as a [metrics](crate::metrics) label
+    #[must_use]
+    pub const fn label(&self) -> &'static str {
+        match self {
+            Self::ApiError(error) => match error {
+                TgApiError::Generic(_) => "generic",
+                TgApiError::RetryAfter(_) => "retry_after",
+                TgApiError::NotFound(_) => "not_found",
+                TgApiError::Unauthorized(_) => "unauthorized",
+                TgApiError::Conflict(_) => "conflict",
+                TgApiError::BadGateway(_) => "bad_gateway",
+                TgApiError::GatewayTimeout(_) => "gateway_timeout",
+            },
+            Self::RequestError(_) => "request",
+            Self::SerdeError(_) => "serde",
+            Self::IO(_) => "io",
+            Self::FileTooBig { .. } => "file_too_big",
+            Self::TransportError(_) => "transport",
+        }
+    }
 }
 
 #[allow(clippy::fallible_impl_from)]
@@ -113,6 +157,39 @@ pub enum TgApiError {
     GatewayTimeout(GenericApiErrorParams),
 }
 
+impl TgApiError {
+    #[must_use]
+    pub const fn params(&self) -> &GenericApiErrorParams {
+        match self {
+            Self::Generic(params)
+            | Self::RetryAfter(params)
+            | Self::NotFound(params)
+            | Self::Unauthorized(params)
+            | Self::Conflict(params)
+            | Self::BadGateway(params)
+            | Self::GatewayTimeout(params) => params,
+        }
+    }
+
+    pub(crate) const fn params_mut(&mut self) -> &mut GenericApiErrorParams {
+        match self {
+            Self::Generic(params)
+            | Self::RetryAfter(params)
+            | Self::NotFound(params)
+            | Self::Unauthorized(params)
+            | Self::Conflict(params)
+            | Self::BadGateway(params)
+            | Self::GatewayTimeout(params) => params,
+        }
+    }
+
+    /// See [`GenericApiErrorParams::kind`]
+    #[must_use]
+    pub fn kind(&self) -> ApiErrorKind {
+        self.params().kind()
+    }
+}
+
 impl<ReturnType> From<TgApiResponse<ReturnType>> for TgApiError {
     fn from(value: TgApiResponse<ReturnType>) -> Self {
         match value.error_code {
@@ -166,3 +243,118 @@ impl Display for GenericApiErrorParams {
         }
     }
 }
+
+impl GenericApiErrorParams {
+    /// Classify the error by its code, description and parameters
+    ///
+    /// Note: descriptions are not a part of the documented API, [`ApiErrorKind::Other`] is returned for unknown ones
+    #[must_use]
+    pub fn kind(&self) -> ApiErrorKind {
+        if let Some(migrate_to_chat_id) =
+            self.parameters.as_ref().and_then(|p| p.migrate_to_chat_id)
+        {
+            return ApiErrorKind::ChatMigrated { migrate_to_chat_id };
+        }
+
+        let description = self
+            .description
+            .as_deref()
+            .unwrap_or_default()
+            .to_lowercase();
+        let has = |pattern: &str| description.contains(pattern);
+
+        match self.error_code {
+            400 if has("message is not modified") => ApiErrorKind::MessageNotModified,
+            400 if has("message to edit not found") => ApiErrorKind::MessageToEditNotFound,
+            400 if has("message to delete not found") => ApiErrorKind::MessageToDeleteNotFound,
+            400 if has("message can't be edited") => ApiErrorKind::MessageCantBeEdited,
+            400 if has("message can't be deleted") => ApiErrorKind::MessageCantBeDeleted,
+            400 if has("message to reply not found") || has("replied message not found") => {
+                ApiErrorKind::ReplyMessageNotFound
+            }
+            400 if has("message thread not found") => ApiErrorKind::MessageThreadNotFound,
+            400 if has("message is too long") => ApiErrorKind::MessageTooLong,
+            400 if has("caption is too long") => ApiErrorKind::CaptionTooLong,
+            400 if has("can't parse entities") => ApiErrorKind::CantParseEntities {
+                byte_offset: description
+                    .split_once("byte offset ")
+                    .and_then(|(_, rest)| {
+                        let end = rest
+                            .find(|c: char| !c.is_ascii_digit())
+                            .unwrap_or(rest.len());
+                        rest[..end].parse().ok()
+                    }),
+            },
+            400 if has("chat not found") => ApiErrorKind::ChatNotFound,
+            400 if has("user not found") => ApiErrorKind::UserNotFound,
+            400 if has("wrong file identifier") || has("wrong remote file identifier") => {
+                ApiErrorKind::WrongFileId
+            }
+            400 if has("query is too old") => ApiErrorKind::QueryTooOld,
+            400 | 403
+                if has("not enough rights")
+                    || has("have no rights")
+                    || has("administrator rights")
+                    || has("chat_admin_required") =>
+            {
+                ApiErrorKind::NotEnoughRights
+            }
+            403 if has("bot was blocked by the user") => ApiErrorKind::BotBlockedByUser,
+            403 if has("user is deactivated") => ApiErrorKind::UserDeactivated,
+            403 if has("bot was kicked") => ApiErrorKind::BotKicked,
+            403 if has("bot is not a member") => ApiErrorKind::BotNotMember,
+            403 if has("can't initiate conversation") => ApiErrorKind::CantInitiateConversation,
+            _ => ApiErrorKind::Other,
+        }
+    }
+}
+
+/// Meaning of a Bot API error, see [`GenericApiErrorParams::kind`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ApiErrorKind {
+    /// New content and reply markup of the message are the same as the current ones
+    MessageNotModified,
+    MessageToEditNotFound,
+    MessageToDeleteNotFound,
+    MessageCantBeEdited,
+
+    /// The message is too old, or the bot has no rights to delete it
+    MessageCantBeDeleted,
+    ReplyMessageNotFound,
+    MessageThreadNotFound,
+    MessageTooLong,
+    CaptionTooLong,
+
+    /// Invalid formatting, `byte_offset` in the text is set if the description contains it
+    CantParseEntities {
+        byte_offset: Option<usize>,
+    },
+    ChatNotFound,
+    UserNotFound,
+    WrongFileId,
+
+    /// Callback query can't be answered anymore
+    QueryTooOld,
+
+    /// The bot lacks administrator rights or permissions required for the action
+    NotEnoughRights,
+    BotBlockedByUser,
+
+    /// The user's account was deleted
+    UserDeactivated,
+
+    /// The bot was removed from the group, supergroup or channel
+    BotKicked,
+    BotNotMember,
+
+    /// The user has never started a private chat with the bot
+    CantInitiateConversation,
+
+    /// The group was upgraded to a supergroup, which should be used instead
+    ChatMigrated {
+        migrate_to_chat_id: i64,
+    },
+
+    /// Any other error, see [`GenericApiErrorParams::description`]
+    Other,
+}
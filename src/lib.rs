@@ -30,13 +30,38 @@
     clippy::large_enum_variant
 )]
 
+// Lets derive macros refer to `::conogram` inside this crate too
+extern crate self as conogram;
+
 mod chat_member_cache;
 
 pub mod api;
+pub mod batch;
+pub mod bot_pool;
+pub mod broadcast;
+pub mod callback_data;
+pub mod cassette;
+pub mod chat_migrations;
 pub mod client;
+pub mod commands;
+pub mod dialogue;
+pub mod dispatcher;
 pub mod errors;
+pub mod metrics;
+#[cfg(feature = "mock-server")]
+pub mod mock_server;
+pub mod offset_store;
+pub mod pagination;
+pub mod polling;
+pub mod rate_limiter;
+pub mod redact;
 pub mod request;
+pub mod retry;
+pub mod scheduler;
 pub mod server_config;
+pub mod transport;
+#[cfg(feature = "webhook")]
+pub mod webhook;
 
 pub mod entities;
 pub mod methods;
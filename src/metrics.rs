@@ -0,0 +1,303 @@
+//! Request metrics: latency, errors, retries, flood waits and uploads
+//!
+//! ```rust, ignore
+//! let metrics = Arc::new(PrometheusMetrics::new());
+//! api.set_metrics_sink(Some(metrics.clone()));
+//!
+//! // Serve it from your `/metrics` endpoint
+//! let body = metrics.render();
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fmt::{Debug, Display, Write},
+    time::Duration,
+};
+
+use dashmap::DashMap;
+
+use crate::{entities::misc::chat_id::ChatId, errors::ConogramErrorType};
+
+/// Upper bounds of [PrometheusMetrics]' request duration histogram buckets, in seconds
+pub const DEFAULT_BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// Finished Bot API call, passed to [`MetricsSink::record_request`]
+#[derive(Debug, Clone, Copy)]
+pub struct RequestMetrics<'a> {
+    /// Bot API method name, e.g. `sendMessage`
+    pub method: &'a str,
+
+    /// Time from sending the request to parsing the response, `getUpdates` takes up to its polling timeout
+    pub duration: Duration,
+
+    /// Size of uploaded local files
+    pub upload_bytes: u64,
+
+    /// `None` if the request succeeded
+    pub error: Option<&'a ConogramErrorType>,
+}
+
+/// Receives metrics of an [Api](crate::api::Api), see [`Api::set_metrics_sink`](crate::api::Api::set_metrics_sink)
+///
+/// Methods are called on request hot paths, so they should be cheap and must not block
+pub trait MetricsSink: Debug + Send + Sync {
+    /// Called after each attempt of a request, retries included
+    fn record_request(&self, request: &RequestMetrics<'_>);
+
+    /// Called before a failed request is retried by [`RequestT::wrap`](crate::request::RequestT::wrap)
+    fn record_retry(&self, method: &str, error: &ConogramErrorType) {
+        let _ = (method, error);
+    }
+
+    /// Called when a request hits a flood wait, `chat_id` is `None` for limits not bound to a chat
+    fn record_flood_wait(&self, method: &str, chat_id: Option<&ChatId>, retry_after: Duration) {
+        let _ = (method, chat_id, retry_after);
+    }
+
+    /// Called when a request is answered from a cache, e.g. the [ChatMember](crate::entities::chat_member::ChatMember) cache
+    fn record_cache_hit(&self, method: &str) {
+        let _ = method;
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MethodMetrics {
+    /// Cumulative, one per bucket
+    bucket_counts: Vec<u64>,
+    count: u64,
+    duration_sum: f64,
+    upload_bytes: u64,
+}
+
+/// [MetricsSink] which keeps metrics in memory and renders them in the Prometheus text format
+///
+/// Exposed metrics:
+/// * `conogram_request_duration_seconds` histogram by `method`
+/// * `conogram_request_errors_total` by `method` and `error` ([`ConogramErrorType::label`])
+/// * `conogram_retries_total` by `method` and `error`
+/// * `conogram_flood_wait_seconds_total` by `chat_id`, empty for limits not bound to a chat
+/// * `conogram_upload_bytes_total` by `method`
+/// * `conogram_cache_hits_total` by `method`
+///
+/// Note: flood waits are labeled by chat, so there may be many series if the bot hits limits in many chats
+#[derive(Debug)]
+pub struct PrometheusMetrics {
+    buckets: Vec<f64>,
+    methods: DashMap<String, MethodMetrics>,
+    errors: DashMap<(String, &'static str), u64>,
+    retries: DashMap<(String, &'static str), u64>,
+    flood_waits: DashMap<String, Duration>,
+    cache_hits: DashMap<String, u64>,
+}
+
+impl Default for PrometheusMetrics {
+    fn default() -> Self {
+        Self {
+            buckets: DEFAULT_BUCKETS.to_vec(),
+            methods: DashMap::new(),
+            errors: DashMap::new(),
+            retries: DashMap::new(),
+            flood_waits: DashMap::new(),
+            cache_hits: DashMap::new(),
+        }
+    }
+}
+
+impl PrometheusMetrics {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Upper bounds of request duration histogram buckets in seconds, [`DEFAULT_BUCKETS`] by default
+    #[must_use]
+    pub fn buckets(mut self, buckets: impl IntoIterator<Item = f64>) -> Self {
+        self.buckets = buckets.into_iter().filter(|le| le.is_finite()).collect();
+        self.buckets.sort_by(f64::total_cmp);
+        self.buckets.dedup();
+        self
+    }
+
+    /// Metrics in the Prometheus text exposition format
+    #[must_use]
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let methods = self
+            .methods
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect::<BTreeMap<_, _>>();
+        self.render_durations(&mut out, &methods);
+
+        counter(
+            &mut out,
+            "conogram_request_errors_total",
+            "Failed Bot API requests by error",
+            sorted(&self.errors)
+                .into_iter()
+                .map(|((method, error), count)| {
+                    (
+                        format!("method=\"{}\",error=\"{error}\"", escape(&method)),
+                        count,
+                    )
+                }),
+        );
+        counter(
+            &mut out,
+            "conogram_retries_total",
+            "Retries of failed Bot API requests by error",
+            sorted(&self.retries)
+                .into_iter()
+                .map(|((method, error), count)| {
+                    (
+                        format!("method=\"{}\",error=\"{error}\"", escape(&method)),
+                        count,
+                    )
+                }),
+        );
+        counter(
+            &mut out,
+            "conogram_flood_wait_seconds_total",
+            "Flood wait time returned by the Bot API by chat",
+            sorted(&self.flood_waits)
+                .into_iter()
+                .map(|(chat_id, retry_after)| {
+                    (
+                        format!("chat_id=\"{}\"", escape(&chat_id)),
+                        retry_after.as_secs_f64(),
+                    )
+                }),
+        );
+        counter(
+            &mut out,
+            "conogram_upload_bytes_total",
+            "Size of files uploaded with Bot API requests",
+            methods
+                .iter()
+                .filter(|(_, metrics)| metrics.upload_bytes > 0)
+                .map(|(method, metrics)| {
+                    (
+                        format!("method=\"{}\"", escape(method)),
+                        metrics.upload_bytes,
+                    )
+                }),
+        );
+        counter(
+            &mut out,
+            "conogram_cache_hits_total",
+            "Bot API requests answered from a cache",
+            sorted(&self.cache_hits)
+                .into_iter()
+                .map(|(method, count)| (format!("method=\"{}\"", escape(&method)), count)),
+        );
+
+        out
+    }
+
+    fn render_durations(&self, out: &mut String, methods: &BTreeMap<String, MethodMetrics>) {
+        let name = "conogram_request_duration_seconds";
+        header(out, name, "histogram", "Duration of Bot API requests");
+
+        for (method, metrics) in methods {
+            let method = escape(method);
+            for (le, bucket_count) in self.buckets.iter().zip(&metrics.bucket_counts) {
+                let _ = writeln!(
+                    out,
+                    "{name}_bucket{{method=\"{method}\",le=\"{le}\"}} {bucket_count}"
+                );
+            }
+            let count = metrics.count;
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{method=\"{method}\",le=\"+Inf\"}} {count}"
+            );
+            let _ = writeln!(
+                out,
+                "{name}_sum{{method=\"{method}\"}} {}",
+                metrics.duration_sum
+            );
+            let _ = writeln!(out, "{name}_count{{method=\"{method}\"}} {count}");
+        }
+    }
+}
+
+impl MetricsSink for PrometheusMetrics {
+    fn record_request(&self, request: &RequestMetrics<'_>) {
+        let seconds = request.duration.as_secs_f64();
+
+        let mut metrics = self
+            .methods
+            .entry(request.method.to_owned())
+            .or_insert_with(|| MethodMetrics {
+                bucket_counts: vec![0; self.buckets.len()],
+                count: 0,
+                duration_sum: 0.0,
+                upload_bytes: 0,
+            });
+        for (le, bucket_count) in self.buckets.iter().zip(&mut metrics.bucket_counts) {
+            if seconds <= *le {
+                *bucket_count += 1;
+            }
+        }
+        metrics.count += 1;
+        metrics.duration_sum += seconds;
+        metrics.upload_bytes += request.upload_bytes;
+        drop(metrics);
+
+        if let Some(error) = request.error {
+            *self
+                .errors
+                .entry((request.method.to_owned(), error.label()))
+                .or_default() += 1;
+        }
+    }
+
+    fn record_retry(&self, method: &str, error: &ConogramErrorType) {
+        *self
+            .retries
+            .entry((method.to_owned(), error.label()))
+            .or_default() += 1;
+    }
+
+    fn record_flood_wait(&self, _method: &str, chat_id: Option<&ChatId>, retry_after: Duration) {
+        let chat_id = chat_id.map(ToString::to_string).unwrap_or_default();
+        *self.flood_waits.entry(chat_id).or_default() += retry_after;
+    }
+
+    fn record_cache_hit(&self, method: &str) {
+        *self.cache_hits.entry(method.to_owned()).or_default() += 1;
+    }
+}
+
+fn header(out: &mut String, name: &str, type_: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {type_}");
+}
+
+/// Counter with `samples` of formatted labels and values
+fn counter(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    samples: impl IntoIterator<Item = (String, impl Display)>,
+) {
+    header(out, name, "counter", help);
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{name}{{{labels}}} {value}");
+    }
+}
+
+fn sorted<K: Ord + Clone + std::hash::Hash, V: Copy>(map: &DashMap<K, V>) -> BTreeMap<K, V> {
+    map.iter()
+        .map(|entry| (entry.key().clone(), *entry.value()))
+        .collect()
+}
+
+/// Escape a label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
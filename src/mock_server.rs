@@ -0,0 +1,565 @@
+//! In-process Bot API server for testing bots without the network
+//!
+//! ```rust, ignore
+//! let server = MockServer::start().await?;
+//! let api = server.api();
+//!
+//! server.respond("sendMessage", MockResponse::retry_after(1));
+//! server.push_update(update);
+//!
+//! run_bot_once(&api).await;
+//!
+//! assert_eq!(server.calls_to("sendMessage").len(), 2);
+//! ```
+
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{
+        Arc, Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicI64, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    Request, Response, StatusCode,
+    body::{Bytes, Incoming},
+    header::CONTENT_TYPE,
+    server::conn::http1,
+    service::service_fn,
+};
+use hyper_util::rt::TokioIo;
+use serde::Serialize;
+use serde_json::{Value, json};
+use tokio::{net::TcpListener, sync::Notify, task::JoinHandle};
+
+use crate::{
+    api::{Api, ApiConfig},
+    entities::update::Update,
+    server_config::ApiServerConfig,
+};
+
+/// Long polling requests are answered after this time at most, regardless of the requested timeout
+const MAX_POLLING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Token used by [`MockServer::api`]
+pub const MOCK_BOT_TOKEN: &str = "123456:MOCK_TOKEN";
+
+/// Bot API call received by the [MockServer]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockCall {
+    pub method: String,
+
+    /// JSON params, for multipart requests uploaded files are replaced with `"<file filename>"`
+    pub params: Value,
+}
+
+/// Response returned by the [MockServer] for a scripted call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockResponse {
+    Ok(Value),
+    Error {
+        error_code: i64,
+        description: String,
+        parameters: Option<Value>,
+    },
+}
+
+impl MockResponse {
+    /// Successful response with `result`
+    ///
+    /// # Panics
+    /// If `result` can't be serialized
+    pub fn ok(result: impl Serialize) -> Self {
+        Self::Ok(serde_json::to_value(result).expect("Mock response must be serializable"))
+    }
+
+    pub fn error(error_code: i64, description: impl Into<String>) -> Self {
+        Self::Error {
+            error_code,
+            description: description.into(),
+            parameters: None,
+        }
+    }
+
+    /// 429 with `retry_after` parameter
+    #[must_use]
+    pub fn retry_after(seconds: i64) -> Self {
+        Self::Error {
+            error_code: 429,
+            description: format!("Too Many Requests: retry after {seconds}"),
+            parameters: Some(json!({ "retry_after": seconds })),
+        }
+    }
+
+    /// 400 with `migrate_to_chat_id` parameter
+    #[must_use]
+    pub fn migrate_to_chat_id(chat_id: i64) -> Self {
+        Self::Error {
+            error_code: 400,
+            description: "Bad Request: group chat was upgraded to a supergroup chat".into(),
+            parameters: Some(json!({ "migrate_to_chat_id": chat_id })),
+        }
+    }
+
+    #[must_use]
+    pub fn bad_gateway() -> Self {
+        Self::error(502, "Bad Gateway")
+    }
+
+    #[must_use]
+    pub fn gateway_timeout() -> Self {
+        Self::error(504, "Gateway Timeout")
+    }
+
+    /// 409, returned by getUpdates when another instance is polling
+    #[must_use]
+    pub fn conflict() -> Self {
+        Self::error(
+            409,
+            "Conflict: terminated by other getUpdates request; make sure that only one bot instance is running",
+        )
+    }
+
+    #[must_use]
+    pub fn unauthorized() -> Self {
+        Self::error(401, "Unauthorized")
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::Ok(_) => StatusCode::OK,
+            Self::Error { error_code, .. } => u16::try_from(*error_code)
+                .ok()
+                .and_then(|code| StatusCode::from_u16(code).ok())
+                .unwrap_or(StatusCode::BAD_REQUEST),
+        }
+    }
+
+    fn to_body(&self) -> Value {
+        match self {
+            Self::Ok(result) => json!({ "ok": true, "result": result }),
+            Self::Error {
+                error_code,
+                description,
+                parameters,
+            } => {
+                let mut body = json!({
+                    "ok": false,
+                    "error_code": error_code,
+                    "description": description,
+                });
+                if let Some(parameters) = parameters {
+                    body["parameters"] = parameters.clone();
+                }
+                body
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct MockState {
+    calls: Mutex<Vec<MockCall>>,
+
+    /// Responses returned once, in order
+    scripted: Mutex<HashMap<String, VecDeque<MockResponse>>>,
+    /// Responses returned when there are no scripted ones
+    persistent: Mutex<HashMap<String, MockResponse>>,
+
+    updates: Mutex<VecDeque<Update>>,
+    updates_notify: Notify,
+    last_update_id: AtomicI64,
+    last_message_id: AtomicI64,
+
+    /// Downloadable files by `file_id`
+    files: Mutex<HashMap<String, Bytes>>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Bot API server running on a random local port
+///
+/// Notes:
+/// * Every call is recorded, see [`MockServer::calls`]
+/// * Calls are answered with responses scripted via [`MockServer::respond`] and [`MockServer::respond_always`].
+///   Otherwise `getUpdates` returns [pushed](MockServer::push_update) updates, `getMe` returns a bot user,
+///   `getFile` returns [added](MockServer::add_file) files, message sending methods return a message built from the params
+///   and other methods return `true`
+/// * The server is stopped on drop
+pub struct MockServer {
+    local_addr: SocketAddr,
+    state: Arc<MockState>,
+    task: JoinHandle<()>,
+}
+
+impl std::fmt::Debug for MockServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockServer")
+            .field("local_addr", &self.local_addr)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MockServer {
+    pub async fn start() -> std::io::Result<Self> {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        let local_addr = listener.local_addr()?;
+        let state = Arc::new(MockState::default());
+
+        let task = tokio::spawn(Self::accept_loop(listener, state.clone()));
+
+        Ok(Self {
+            local_addr,
+            state,
+            task,
+        })
+    }
+
+    #[must_use]
+    pub fn url(&self) -> String {
+        format!("http://{}", self.local_addr)
+    }
+
+    #[must_use]
+    pub fn server_config(&self) -> ApiServerConfig {
+        ApiServerConfig::local(Some(self.url()), false)
+    }
+
+    #[must_use]
+    pub fn api_config(&self) -> ApiConfig {
+        ApiConfig::new(MOCK_BOT_TOKEN, Some(self.server_config()))
+    }
+
+    /// [Api] connected to this server, with short polling timeout
+    #[must_use]
+    pub fn api(&self) -> Api {
+        let mut api = Api::new(self.api_config());
+        api.set_polling_timeout(1);
+        api
+    }
+
+    /// Answer the next call of `method` with `response`. Responses are returned in order of scripting
+    pub fn respond(&self, method: impl Into<String>, response: MockResponse) {
+        lock(&self.state.scripted)
+            .entry(method.into())
+            .or_default()
+            .push_back(response);
+    }
+
+    /// Answer all calls of `method` with `response`, after scripted responses are used up
+    pub fn respond_always(&self, method: impl Into<String>, response: MockResponse) {
+        lock(&self.state.persistent).insert(method.into(), response);
+    }
+
+    /// Queue the update for `getUpdates`. `update_id` is assigned automatically if it's 0
+    pub fn push_update(&self, mut update: Update) {
+        if update.update_id == 0 {
+            update.update_id = self.state.last_update_id.fetch_add(1, Ordering::Relaxed) + 1;
+        } else {
+            self.state
+                .last_update_id
+                .fetch_max(update.update_id, Ordering::Relaxed);
+        }
+
+        lock(&self.state.updates).push_back(update);
+        self.state.updates_notify.notify_waiters();
+    }
+
+    /// Updates which were not confirmed by `getUpdates` offset yet
+    #[must_use]
+    pub fn pending_updates(&self) -> Vec<Update> {
+        lock(&self.state.updates).iter().cloned().collect()
+    }
+
+    /// All calls received so far, in order
+    #[must_use]
+    pub fn calls(&self) -> Vec<MockCall> {
+        lock(&self.state.calls).clone()
+    }
+
+    /// Calls of `method` received so far, in order
+    #[must_use]
+    pub fn calls_to(&self, method: &str) -> Vec<MockCall> {
+        lock(&self.state.calls)
+            .iter()
+            .filter(|call| call.method == method)
+            .cloned()
+            .collect()
+    }
+
+    pub fn clear_calls(&self) {
+        lock(&self.state.calls).clear();
+    }
+
+    /// Make the file available via `getFile` and downloadable at the returned `file_path`
+    pub fn add_file(&self, file_id: impl Into<String>, contents: impl Into<Bytes>) {
+        lock(&self.state.files).insert(file_id.into(), contents.into());
+    }
+
+    async fn accept_loop(listener: TcpListener, state: Arc<MockState>) {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                continue;
+            };
+
+            let state = state.clone();
+            tokio::spawn(async move {
+                let service = service_fn(|request| Self::handle(request, state.clone()));
+                if let Err(err) = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    log::debug!("Mock server connection failed: {err}");
+                }
+            });
+        }
+    }
+
+    async fn handle(
+        request: Request<Incoming>,
+        state: Arc<MockState>,
+    ) -> Result<Response<Full<Bytes>>, Infallible> {
+        // File downloads: `/file/bot<token>/files/<file_id>`
+        if request.uri().path().starts_with("/file/") {
+            let file_id = request.uri().path().rsplit('/').next().unwrap_or_default();
+            let contents = lock(&state.files).get(file_id).cloned();
+
+            let mut response = Response::new(Full::new(contents.clone().unwrap_or_default()));
+            if contents.is_none() {
+                *response.status_mut() = StatusCode::NOT_FOUND;
+            }
+            return Ok(response);
+        }
+
+        // Path is `/bot<token>/<method>` or `/bot<token>/test/<method>`
+        let method = request
+            .uri()
+            .path()
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .to_owned();
+
+        let content_type = request
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_owned();
+
+        let body = match request.into_body().collect().await {
+            Ok(body) => body.to_bytes(),
+            Err(err) => {
+                log::warn!("Mock server failed to read request body: {err}");
+                return Ok(Self::response(&MockResponse::error(400, "Bad Request")));
+            }
+        };
+
+        let params =
+            if let Some(boundary) = content_type.strip_prefix("multipart/form-data; boundary=") {
+                parse_multipart(&body, boundary)
+            } else if body.is_empty() {
+                json!({})
+            } else {
+                serde_json::from_slice(&body).unwrap_or(Value::Null)
+            };
+
+        lock(&state.calls).push(MockCall {
+            method: method.clone(),
+            params: params.clone(),
+        });
+
+        let scripted = lock(&state.scripted)
+            .get_mut(&method)
+            .and_then(VecDeque::pop_front)
+            .or_else(|| lock(&state.persistent).get(&method).cloned());
+
+        let response = match scripted {
+            Some(response) => response,
+            None => Self::default_response(&state, &method, &params).await,
+        };
+
+        Ok(Self::response(&response))
+    }
+
+    async fn default_response(state: &MockState, method: &str, params: &Value) -> MockResponse {
+        match method {
+            "getUpdates" => MockResponse::ok(Self::get_updates(state, params).await),
+            "getMe" => MockResponse::ok(Self::bot_user()),
+            "getFile" => {
+                let file_id = params["file_id"].as_str().unwrap_or_default();
+                match lock(&state.files).get(file_id) {
+                    Some(contents) => MockResponse::ok(json!({
+                        "file_id": file_id,
+                        "file_unique_id": file_id,
+                        "file_size": contents.len(),
+                        "file_path": format!("files/{file_id}"),
+                    })),
+                    None => MockResponse::error(400, "Bad Request: invalid file_id"),
+                }
+            }
+            "copyMessage" => MockResponse::ok(json!({
+                "message_id": state.last_message_id.fetch_add(1, Ordering::Relaxed) + 1
+            })),
+            "copyMessages" | "forwardMessages" => {
+                let count = params["message_ids"].as_array().map_or(1, Vec::len);
+                MockResponse::ok(
+                    (0..count)
+                        .map(|_| {
+                            json!({
+                                "message_id": state.last_message_id.fetch_add(1, Ordering::Relaxed) + 1
+                            })
+                        })
+                        .collect::<Vec<_>>(),
+                )
+            }
+            "sendMediaGroup" => {
+                let count = params["media"].as_array().map_or(1, Vec::len);
+                MockResponse::ok(
+                    (0..count)
+                        .map(|_| Self::message(state, params))
+                        .collect::<Vec<_>>(),
+                )
+            }
+            _ if method.starts_with("send") && method != "sendChatAction"
+                || method == "forwardMessage" =>
+            {
+                MockResponse::ok(Self::message(state, params))
+            }
+            _ => MockResponse::ok(true),
+        }
+    }
+
+    async fn get_updates(state: &MockState, params: &Value) -> Vec<Update> {
+        let offset = params["offset"].as_i64().unwrap_or_default();
+        let limit = params["limit"].as_u64().unwrap_or(100) as usize;
+        let timeout = Duration::from_secs(params["timeout"].as_u64().unwrap_or_default())
+            .min(MAX_POLLING_TIMEOUT);
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            // Registered before checking the queue, so an update pushed in between is not missed
+            let notified = state.updates_notify.notified();
+
+            {
+                let mut updates = lock(&state.updates);
+                // Updates below the offset are confirmed
+                updates.retain(|update| update.update_id >= offset);
+                if !updates.is_empty() {
+                    return updates.iter().take(limit).cloned().collect();
+                }
+            }
+
+            if tokio::time::timeout_at(deadline, notified).await.is_err() {
+                return vec![];
+            }
+        }
+    }
+
+    fn bot_user() -> Value {
+        json!({
+            "id": MOCK_BOT_TOKEN.split(':').next().unwrap_or_default().parse::<i64>().unwrap_or_default(),
+            "is_bot": true,
+            "first_name": "Mock Bot",
+            "username": "mock_bot",
+        })
+    }
+
+    /// Message "sent" by the bot, built from the request params
+    fn message(state: &MockState, params: &Value) -> Value {
+        let chat = match &params["chat_id"] {
+            Value::String(username) => json!({
+                "id": 0,
+                "type": "channel",
+                "username": username.trim_start_matches('@'),
+            }),
+            chat_id => {
+                let id = chat_id.as_i64().unwrap_or_default();
+                json!({
+                    "id": id,
+                    "type": if id > 0 { "private" } else { "supergroup" },
+                })
+            }
+        };
+
+        let date = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let mut message = json!({
+            "message_id": state.last_message_id.fetch_add(1, Ordering::Relaxed) + 1,
+            "date": date,
+            "chat": chat,
+            "from": Self::bot_user(),
+        });
+        for field in [
+            "text",
+            "caption",
+            "entities",
+            "caption_entities",
+            "reply_markup",
+        ] {
+            if !params[field].is_null() {
+                message[field] = params[field].clone();
+            }
+        }
+        message
+    }
+
+    fn response(response: &MockResponse) -> Response<Full<Bytes>> {
+        let body = serde_json::to_vec(&response.to_body()).unwrap_or_default();
+        let mut http_response = Response::new(Full::new(Bytes::from(body)));
+        *http_response.status_mut() = response.status();
+        http_response.headers_mut().insert(
+            CONTENT_TYPE,
+            hyper::http::HeaderValue::from_static("application/json"),
+        );
+        http_response
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Minimal `multipart/form-data` parser: text fields are parsed as JSON if possible, files are replaced with a placeholder
+fn parse_multipart(body: &[u8], boundary: &str) -> Value {
+    let body = String::from_utf8_lossy(body);
+    let delimiter = format!("--{}", boundary.trim_matches('"'));
+    let mut params = serde_json::Map::new();
+
+    for part in body.split(delimiter.as_str()) {
+        let Some((headers, content)) = part.split_once("\r\n\r\n") else {
+            continue;
+        };
+        let Some(name) = header_param(headers, "name") else {
+            continue;
+        };
+        let content = content.strip_suffix("\r\n").unwrap_or(content);
+
+        let value = if let Some(filename) = header_param(headers, "filename") {
+            Value::String(format!("<file {filename}>"))
+        } else {
+            serde_json::from_str(content).unwrap_or_else(|_| Value::String(content.to_owned()))
+        };
+        params.insert(name.to_owned(), value);
+    }
+
+    Value::Object(params)
+}
+
+fn header_param<'a>(headers: &'a str, param: &str) -> Option<&'a str> {
+    let pattern = format!(" {param}=\"");
+    let start = headers.find(&pattern)? + pattern.len();
+    let len = headers[start..].find('"')?;
+    Some(&headers[start..start + len])
+}
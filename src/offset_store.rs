@@ -0,0 +1,97 @@
+use std::{
+    fmt::Debug,
+    io,
+    path::PathBuf,
+    sync::atomic::{AtomicI64, Ordering},
+};
+
+/// Persistent storage for the [getUpdates](https://core.telegram.org/bots/api/#getupdates) offset
+///
+/// Notes:
+/// * Methods are called synchronously from [`Api`](crate::api::Api) every time the offset moves, so they should be cheap
+/// * Errors returned by [`OffsetStore::store`] are logged and don't stop polling
+pub trait OffsetStore: Debug + Send + Sync {
+    /// Returns `None` if no offset was stored yet
+    fn load(&self) -> io::Result<Option<i64>>;
+
+    fn store(&self, offset: i64) -> io::Result<()>;
+}
+
+/// When the offset is moved past received updates
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AckMode {
+    /// Offset is moved as soon as updates are received, updates which were not handled before a crash are lost
+    #[default]
+    OnReceive,
+
+    /// Offset is moved only by [`Api::ack_update`](crate::api::Api::ack_update) (called by the [Dispatcher](crate::dispatcher::Dispatcher) after handlers finish),
+    /// so unacknowledged updates are received again after a restart (at-least-once delivery)
+    ///
+    /// Note: `getUpdates` returns at most 100 updates starting from the oldest unacknowledged one, so a handler which
+    /// runs long (e.g. waits in [`Dialogue::prompt`](crate::dialogue::Dialogue::prompt)) blocks delivery of newer updates
+    /// once 100 updates pile up behind its update
+    Manual,
+}
+
+/// Keeps the offset in memory, e.g. to share it between [`Api`](crate::api::Api) instances
+#[derive(Debug, Default)]
+pub struct MemoryOffsetStore(AtomicI64);
+
+impl MemoryOffsetStore {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self(AtomicI64::new(0))
+    }
+}
+
+impl OffsetStore for MemoryOffsetStore {
+    fn load(&self) -> io::Result<Option<i64>> {
+        let offset = self.0.load(Ordering::Relaxed);
+        Ok((offset != 0).then_some(offset))
+    }
+
+    fn store(&self, offset: i64) -> io::Result<()> {
+        self.0.store(offset, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Keeps the offset as text in a file, which is replaced atomically on every write
+#[derive(Debug, Clone)]
+pub struct FileOffsetStore {
+    path: PathBuf,
+}
+
+impl FileOffsetStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn tmp_path(&self) -> PathBuf {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        tmp_path.into()
+    }
+}
+
+impl OffsetStore for FileOffsetStore {
+    fn load(&self) -> io::Result<Option<i64>> {
+        let content = match std::fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        content
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    fn store(&self, offset: i64) -> io::Result<()> {
+        let tmp_path = self.tmp_path();
+        std::fs::write(&tmp_path, offset.to_string())?;
+        std::fs::rename(tmp_path, &self.path)
+    }
+}
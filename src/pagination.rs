@@ -0,0 +1,276 @@
+//! Paging through long lists with inline keyboard buttons
+//!
+//! ```rust, ignore
+//! let paginator = Paginator::new("users")?.page_size(6).columns(2);
+//! let buttons = users
+//!     .iter()
+//!     .map(|user| InlineKeyboardButton::callback(&user.name, format!("user:{}", user.id), None, None))
+//!     .collect::<Vec<_>>();
+//!
+//! // Send the first page
+//! message.reply(&api, "Users").reply_markup(paginator.keyboard(&buttons, 0)).await?;
+//!
+//! // Switch pages when navigation buttons are pressed
+//! let dispatcher = Dispatcher::new(api).handler(Handler::callback_query(move |api, query| {
+//!     let (paginator, buttons) = (paginator.clone(), buttons.clone());
+//!     async move {
+//!         if !paginator.handle(&api, &query, &buttons).await? {
+//!             // Not a navigation button, e.g. "user:123"
+//!         }
+//!         Ok(())
+//!     }
+//! }));
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::Api,
+    callback_data::{CallbackCodec, CallbackData, CallbackDataError},
+    entities::{
+        callback_query::CallbackQuery, inline_keyboard_button::InlineKeyboardButton,
+        inline_keyboard_markup::InlineKeyboardMarkup,
+    },
+    errors::{ApiErrorKind, ConogramError},
+    request::RequestT,
+};
+
+/// Max number of buttons in an inline keyboard row
+pub const MAX_ROW_WIDTH: usize = 8;
+
+/// Callback data of navigation buttons: paginator id and page number
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageQuery(pub String, pub usize);
+
+impl CallbackData for PageQuery {
+    const PREFIX: &'static str = "page";
+}
+
+/// Renders a page of buttons with a navigation row: `‹`, page numbers around the current page and `›`
+///
+/// Pages are numbered from 0, the current page number is shown as `· N ·`. Cheap to clone
+#[derive(Debug, Clone)]
+pub struct Paginator {
+    id: String,
+    page_size: usize,
+    columns: usize,
+    max_row_width: usize,
+    page_buttons: usize,
+    codec: CallbackCodec,
+}
+
+impl Paginator {
+    /// `id` tells paginators of the bot apart, it's a part of navigation buttons' callback data,
+    /// so it must be short enough to fit into [`MAX_CALLBACK_DATA_LEN`](crate::callback_data::MAX_CALLBACK_DATA_LEN)
+    pub fn new(id: impl Into<String>) -> Result<Self, CallbackDataError> {
+        let paginator = Self {
+            id: id.into(),
+            page_size: 10,
+            columns: 1,
+            max_row_width: MAX_ROW_WIDTH,
+            page_buttons: 5,
+            codec: CallbackCodec::new(),
+        };
+        paginator
+            .codec
+            .encode(&PageQuery(paginator.id.clone(), usize::MAX))?;
+
+        Ok(paginator)
+    }
+
+    /// Items per page, 10 by default
+    #[must_use]
+    pub fn page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    /// Item buttons per row, 1 by default
+    #[must_use]
+    pub fn columns(mut self, columns: usize) -> Self {
+        self.columns = columns.max(1);
+        self
+    }
+
+    /// Max buttons per row for both items and navigation, [`MAX_ROW_WIDTH`] by default
+    #[must_use]
+    pub fn max_row_width(mut self, max_row_width: usize) -> Self {
+        self.max_row_width = max_row_width.clamp(3, MAX_ROW_WIDTH);
+        self
+    }
+
+    /// Page number buttons shown in the navigation row, 5 by default, 0 to show only `‹` and `›`
+    #[must_use]
+    pub const fn page_buttons(mut self, page_buttons: usize) -> Self {
+        self.page_buttons = page_buttons;
+        self
+    }
+
+    /// Codec for navigation buttons' callback data, e.g. the one used by the [Dispatcher](crate::dispatcher::Dispatcher)
+    #[must_use]
+    pub fn codec(mut self, codec: CallbackCodec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    #[must_use]
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    #[must_use]
+    pub const fn page_count(&self, items: usize) -> usize {
+        items.div_ceil(self.page_size)
+    }
+
+    /// Keyboard with `page` of `items`, out of range pages are clamped
+    #[must_use]
+    pub fn keyboard(&self, items: &[InlineKeyboardButton], page: usize) -> InlineKeyboardMarkup {
+        let page_count = self.page_count(items.len());
+        let page = page.min(page_count.saturating_sub(1));
+
+        let page_items = items
+            .iter()
+            .skip(page * self.page_size)
+            .take(self.page_size)
+            .cloned();
+        self.page_keyboard(page_items, page, page_count)
+    }
+
+    /// Keyboard with items of the current page, e.g. loaded from a database, and the navigation row
+    #[must_use]
+    pub fn page_keyboard(
+        &self,
+        page_items: impl IntoIterator<Item = InlineKeyboardButton>,
+        page: usize,
+        page_count: usize,
+    ) -> InlineKeyboardMarkup {
+        let columns = self.columns.min(self.max_row_width);
+
+        let mut rows = Vec::new();
+        let mut row = Vec::with_capacity(columns);
+        for button in page_items {
+            row.push(button);
+            if row.len() == columns {
+                rows.push(std::mem::replace(&mut row, Vec::with_capacity(columns)));
+            }
+        }
+        if !row.is_empty() {
+            rows.push(row);
+        }
+
+        let navigation = self.navigation_row(page, page_count);
+        if !navigation.is_empty() {
+            rows.push(navigation);
+        }
+
+        InlineKeyboardMarkup::new(rows)
+    }
+
+    /// Navigation buttons, empty if there is a single page
+    #[must_use]
+    pub fn navigation_row(&self, page: usize, page_count: usize) -> Vec<InlineKeyboardButton> {
+        if page_count <= 1 {
+            return vec![];
+        }
+
+        let page = page.min(page_count - 1);
+        let shown = self
+            .page_buttons
+            .min(self.max_row_width - 2)
+            .min(page_count);
+        let first = page.saturating_sub(shown / 2).min(page_count - shown);
+
+        let mut row = Vec::with_capacity(shown + 2);
+        if page > 0 {
+            row.push(self.button("‹", page - 1));
+        }
+        for number in first..first + shown {
+            let text = if number == page {
+                format!("· {} ·", number + 1)
+            } else {
+                (number + 1).to_string()
+            };
+            row.push(self.button(text, number));
+        }
+        if page + 1 < page_count {
+            row.push(self.button("›", page + 1));
+        }
+
+        row
+    }
+
+    fn button(&self, text: impl Into<String>, page: usize) -> InlineKeyboardButton {
+        let data = self
+            .codec
+            .encode(&PageQuery(self.id.clone(), page))
+            .expect("Page query length is checked in Paginator::new");
+        InlineKeyboardButton::callback(text, data, None, None)
+    }
+
+    /// Page requested by the query, `None` if it's not a navigation button of this paginator
+    #[must_use]
+    pub fn decode(&self, query: &CallbackQuery) -> Option<usize> {
+        match self.codec.decode_query::<PageQuery>(query)? {
+            Ok(PageQuery(id, page)) if id == self.id => Some(page),
+            Ok(_) => None,
+            Err(err) => {
+                log::debug!("Invalid page query {:?}: {err}", query.data);
+                None
+            }
+        }
+    }
+
+    /// If the query is a navigation button of this paginator, show the requested page of `items` by editing the message in place
+    /// and answer the query. Returns `false` for other queries
+    pub async fn handle(
+        &self,
+        api: &Api,
+        query: &CallbackQuery,
+        items: &[InlineKeyboardButton],
+    ) -> Result<bool, ConogramError> {
+        let Some(page) = self.decode(query) else {
+            return Ok(false);
+        };
+
+        let keyboard = self.keyboard(items, page);
+        Box::pin(self.show(api, query, keyboard)).await?;
+        Ok(true)
+    }
+
+    /// Replace the keyboard of the query's message with `keyboard` (e.g. built with [`Paginator::page_keyboard`]) and answer the query
+    pub async fn show(
+        &self,
+        api: &Api,
+        query: &CallbackQuery,
+        keyboard: InlineKeyboardMarkup,
+    ) -> Result<(), ConogramError> {
+        let result = if let Some(message) = query.message() {
+            // Editing to the same keyboard fails with "message is not modified"
+            if message.reply_markup.as_ref() == Some(&keyboard) {
+                Ok(())
+            } else {
+                let request = message.edit_reply_markup(api).reply_markup(keyboard);
+                Box::pin(request.wrap()).await.map(drop)
+            }
+        } else if let Some(inline_message_id) = &query.inline_message_id {
+            let request = api
+                .edit_message_reply_markup()
+                .inline_message_id(inline_message_id)
+                .reply_markup(keyboard);
+            Box::pin(request.wrap()).await.map(drop)
+        } else {
+            Ok(())
+        };
+
+        // Inline messages' keyboards are unknown, the same page may be requested twice
+        if let Err(err) = result
+            && err.api_error_kind() != Some(ApiErrorKind::MessageNotModified)
+        {
+            return Err(err);
+        }
+
+        query.answer(api).wrap().await?;
+        Ok(())
+    }
+}
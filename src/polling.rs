@@ -0,0 +1,125 @@
+use std::{collections::VecDeque, time::Duration};
+
+use futures::{Stream, stream};
+pub use tokio_util::sync::CancellationToken;
+
+use crate::{
+    api::Api,
+    entities::update::Update,
+    errors::{ConogramError, ConogramErrorType, TgApiError},
+};
+
+/// Backoff for transient polling errors starts from this value and doubles up to [MAX_BACKOFF]
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_mins(1);
+
+struct PollingState<'a> {
+    api: &'a Api,
+    cancellation_token: CancellationToken,
+    buffer: VecDeque<Update>,
+    backoff: Duration,
+    finished: bool,
+}
+
+enum PollingStep {
+    Continue,
+    Finish(Option<ConogramError>),
+}
+
+impl PollingState<'_> {
+    async fn next(&mut self) -> Option<Result<Update, ConogramError>> {
+        loop {
+            if let Some(update) = self.buffer.pop_front() {
+                return Some(Ok(update));
+            }
+            if self.finished {
+                return None;
+            }
+
+            let step = tokio::select! {
+                biased;
+
+                () = self.cancellation_token.cancelled() => PollingStep::Finish(None),
+                result = self.api.poll_once() => self.on_poll_result(result).await,
+            };
+
+            if let PollingStep::Finish(err) = step {
+                self.finished = true;
+                if err.is_none() {
+                    log::debug!("Polling was cancelled, committing updates offset");
+                    if let Err(err) = self.api.commit_updates_offset().await {
+                        log::warn!("Failed to commit updates offset: {err}");
+                    }
+                }
+                return err.map(Err);
+            }
+        }
+    }
+
+    async fn on_poll_result(&mut self, result: Result<Vec<Update>, ConogramError>) -> PollingStep {
+        let err = match result {
+            Ok(updates) => {
+                self.backoff = MIN_BACKOFF;
+                self.buffer.extend(updates);
+                return PollingStep::Continue;
+            }
+            Err(err) => err,
+        };
+
+        let wait_for = match &err.type_ {
+            ConogramErrorType::ApiError(
+                TgApiError::Unauthorized(_) | TgApiError::Conflict(_) | TgApiError::NotFound(_),
+            )
+            | ConogramErrorType::SerdeError(_) => return PollingStep::Finish(Some(err)),
+
+            ConogramErrorType::ApiError(TgApiError::RetryAfter(params)) => Duration::from_secs(
+                params
+                    .parameters
+                    .as_ref()
+                    .and_then(|p| p.retry_after)
+                    .unwrap_or_default()
+                    .clamp(1, 600) as u64,
+            ),
+
+            _ => {
+                let wait_for = self.backoff;
+                self.backoff = std::cmp::min(self.backoff * 2, MAX_BACKOFF);
+                wait_for
+            }
+        };
+
+        log::warn!("Polling failed, retrying in {wait_for:?}: {err}");
+        tokio::select! {
+            () = self.cancellation_token.cancelled() => PollingStep::Finish(None),
+            () = tokio::time::sleep(wait_for) => PollingStep::Continue,
+        }
+    }
+}
+
+impl Api {
+    /// Long-polling [Stream] of updates
+    ///
+    /// Notes:
+    /// * Transient errors (network errors, `BadGateway`, `GatewayTimeout`, flood wait etc.) are retried with backoff
+    /// * `Unauthorized`, `NotFound` (invalid bot token), `Conflict` (another instance is running) and update deserialization errors are terminal:
+    ///   the error is returned as the last item of the stream
+    /// * After `cancellation_token` is cancelled, already received updates are still returned,
+    ///   then the offset is committed with one last [getUpdates](https://core.telegram.org/bots/api/#getupdates) call and the stream ends
+    pub fn updates(
+        &self,
+        cancellation_token: CancellationToken,
+    ) -> impl Stream<Item = Result<Update, ConogramError>> + Send + '_ {
+        let state = PollingState {
+            api: self,
+            cancellation_token,
+            buffer: VecDeque::new(),
+            backoff: MIN_BACKOFF,
+            finished: false,
+        };
+
+        stream::unfold(state, |mut state| async move {
+            let item = state.next().await?;
+            Some((item, state))
+        })
+    }
+}
@@ -0,0 +1,256 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use tokio::time::Instant;
+
+use crate::entities::misc::chat_id::ChatId;
+
+/// Requests which send messages and therefore count towards [broadcasting limits](https://core.telegram.org/bots/faq#my-bot-is-hitting-limits-how-do-i-avoid-this)
+const RATE_LIMITED_METHODS: &[&str] = &[
+    "sendMessage",
+    "sendPhoto",
+    "sendAudio",
+    "sendDocument",
+    "sendVideo",
+    "sendAnimation",
+    "sendVoice",
+    "sendVideoNote",
+    "sendLivePhoto",
+    "sendPaidMedia",
+    "sendMediaGroup",
+    "sendLocation",
+    "sendVenue",
+    "sendContact",
+    "sendPoll",
+    "sendChecklist",
+    "sendDice",
+    "sendSticker",
+    "sendInvoice",
+    "sendGame",
+    "sendRichMessage",
+    "copyMessage",
+    "copyMessages",
+    "forwardMessage",
+    "forwardMessages",
+];
+
+/// Stale per-chat buckets are dropped with this interval
+const CLEANUP_INTERVAL: Duration = Duration::from_mins(1);
+
+/// At most `count` requests per `period`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RateLimit {
+    pub count: u32,
+    pub period: Duration,
+}
+
+impl RateLimit {
+    #[must_use]
+    pub const fn new(count: u32, period: Duration) -> Self {
+        Self { count, period }
+    }
+
+    #[must_use]
+    pub const fn per_second(count: u32) -> Self {
+        Self::new(count, Duration::from_secs(1))
+    }
+
+    #[must_use]
+    pub const fn per_minute(count: u32) -> Self {
+        Self::new(count, Duration::from_mins(1))
+    }
+
+    /// Spacing between requests after the burst is used up
+    fn interval(&self) -> Duration {
+        self.period / self.count.max(1)
+    }
+
+    /// How far ahead of the schedule requests are allowed, i.e. the burst size
+    fn tolerance(&self) -> Duration {
+        self.period.saturating_sub(self.interval())
+    }
+}
+
+/// Token bucket, implemented as a virtual schedule: `next_at` is the time the next request would be sent at
+/// if requests were spread evenly
+#[derive(Debug)]
+struct Bucket {
+    next_at: Instant,
+}
+
+impl Bucket {
+    /// Returns when the request may be sent, the slot is reserved right away
+    fn reserve(&mut self, limit: RateLimit, now: Instant) -> Instant {
+        let send_at = std::cmp::max(
+            now,
+            self.next_at.checked_sub(limit.tolerance()).unwrap_or(now),
+        );
+        self.next_at = std::cmp::max(self.next_at, send_at) + limit.interval();
+        send_at
+    }
+
+    /// Don't allow any requests until `until`
+    fn block(&mut self, limit: RateLimit, until: Instant) {
+        self.next_at = std::cmp::max(self.next_at, until + limit.tolerance());
+    }
+}
+
+#[derive(Debug)]
+struct Buckets {
+    global: Bucket,
+    chats: HashMap<ChatId, Bucket>,
+    last_cleanup: Instant,
+}
+
+/// Client-side limiter, which delays message sending requests to stay within Telegram limits
+///
+/// Notes:
+/// * Requests are delayed in order of calling, separately for each chat
+/// * Requests with `allow_paid_broadcast` are not delayed
+/// * Flood waits received from the server block the chat (or all chats if the request had no target chat) for the returned duration
+#[derive(Debug)]
+pub struct RateLimiter {
+    global_limit: RateLimit,
+    private_chat_limit: RateLimit,
+    group_chat_limit: RateLimit,
+
+    buckets: Mutex<Buckets>,
+}
+
+impl Default for RateLimiter {
+    /// Documented limits: 30 messages per second overall, 1 per second in a private chat, 20 per minute in a group
+    fn default() -> Self {
+        let now = Instant::now();
+        Self {
+            global_limit: RateLimit::per_second(30),
+            private_chat_limit: RateLimit::per_second(1),
+            group_chat_limit: RateLimit::per_minute(20),
+            buckets: Mutex::new(Buckets {
+                global: Bucket { next_at: now },
+                chats: HashMap::new(),
+                last_cleanup: now,
+            }),
+        }
+    }
+}
+
+impl RateLimiter {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit for all messages sent by the bot
+    #[must_use]
+    pub const fn global(mut self, limit: RateLimit) -> Self {
+        self.global_limit = limit;
+        self
+    }
+
+    /// Limit for messages sent to a single user
+    #[must_use]
+    pub const fn private_chat(mut self, limit: RateLimit) -> Self {
+        self.private_chat_limit = limit;
+        self
+    }
+
+    /// Limit for messages sent to a single group or channel
+    #[must_use]
+    pub const fn group_chat(mut self, limit: RateLimit) -> Self {
+        self.group_chat_limit = limit;
+        self
+    }
+
+    #[must_use]
+    pub fn is_rate_limited(method: &str) -> bool {
+        RATE_LIMITED_METHODS.contains(&method)
+    }
+
+    const fn chat_limit(&self, chat_id: &ChatId) -> RateLimit {
+        if chat_id.is_user_chat() {
+            self.private_chat_limit
+        } else {
+            self.group_chat_limit
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Buckets> {
+        self.buckets
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    fn reserve_chat(&self, chat_id: &ChatId) -> Instant {
+        let now = Instant::now();
+        let limit = self.chat_limit(chat_id);
+        let mut buckets = self.lock();
+
+        if now.duration_since(buckets.last_cleanup) > CLEANUP_INTERVAL {
+            buckets.chats.retain(|_, bucket| bucket.next_at > now);
+            buckets.last_cleanup = now;
+        }
+
+        buckets
+            .chats
+            .entry(chat_id.clone())
+            .or_insert(Bucket { next_at: now })
+            .reserve(limit, now)
+    }
+
+    fn reserve_global(&self) -> Instant {
+        let now = Instant::now();
+        self.lock().global.reserve(self.global_limit, now)
+    }
+
+    /// Wait until the request can be sent
+    pub(crate) async fn acquire(
+        &self,
+        method: &str,
+        chat_id: Option<&ChatId>,
+        paid_broadcast: bool,
+    ) {
+        if paid_broadcast || !Self::is_rate_limited(method) {
+            return;
+        }
+
+        // Chat slot is awaited first, so requests waiting for a busy chat don't hold global slots
+        if let Some(chat_id) = chat_id {
+            let send_at = self.reserve_chat(chat_id);
+            if send_at > Instant::now() {
+                log::trace!("Delaying {method} in chat {chat_id} until {send_at:?}");
+                tokio::time::sleep_until(send_at).await;
+            }
+        }
+
+        let send_at = self.reserve_global();
+        if send_at > Instant::now() {
+            log::trace!("Delaying {method} until {send_at:?} due to the global limit");
+            tokio::time::sleep_until(send_at).await;
+        }
+    }
+
+    /// Block the chat (or all chats if `chat_id` is `None`) after the server returned a flood wait
+    pub(crate) fn register_flood_wait(
+        &self,
+        method: &str,
+        chat_id: Option<&ChatId>,
+        retry_after: Duration,
+    ) {
+        if !Self::is_rate_limited(method) {
+            return;
+        }
+
+        let until = Instant::now() + retry_after;
+        let mut buckets = self.lock();
+        match chat_id {
+            Some(chat_id) => {
+                let limit = self.chat_limit(chat_id);
+                buckets
+                    .chats
+                    .entry(chat_id.clone())
+                    .or_insert(Bucket { next_at: until })
+                    .block(limit, until);
+            }
+            None => buckets.global.block(self.global_limit, until),
+        }
+    }
+}
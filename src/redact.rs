@@ -0,0 +1,132 @@
+//! Keeping the bot token out of errors and logs
+//!
+//! Request URLs contain the bot token, so transport errors which mention them are scrubbed before they are returned
+//! as [ConogramError]. The secret part of the token is replaced with [`REDACTED`], the bot id is kept:
+//!
+//! ```text
+//! error sending request for url (https://api.telegram.org/bot123456:***/getMe)
+//! ```
+//!
+//! Notes:
+//! * The [Api](crate::api::Api)'s token is redacted in any format, anything else is redacted if it looks like a bot token
+//! * Transport and IO errors which mention the token are replaced with [RedactedError], as their sources may mention it too
+//! * Use [`redact_tokens`] to scrub your own logs, e.g. of webhook URLs containing the token
+
+use std::{borrow::Cow, error::Error, sync::LazyLock};
+
+use regex::Regex;
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::{
+    api::ApiToken,
+    errors::{ConogramError, ConogramErrorType},
+};
+
+/// Replacement for the secret part of bot tokens
+pub const REDACTED: &str = "***";
+
+static TOKEN_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(\d+):[A-Za-z0-9_-]{30,}").expect("Token regex is valid"));
+
+/// Replace the secret part of anything that looks like a bot token in `text`
+#[must_use]
+pub fn redact_tokens(text: &str) -> Cow<'_, str> {
+    TOKEN_REGEX.replace_all(text, format!("${{1}}:{REDACTED}"))
+}
+
+/// Error which mentioned the bot token, with the token redacted from its message
+#[derive(Debug, Error)]
+#[error("{message}")]
+pub struct RedactedError {
+    message: String,
+}
+
+impl RedactedError {
+    #[must_use]
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// `None` if `error` doesn't mention the token
+    fn new(error: &(dyn Error + '_), token: Option<&ApiToken>) -> Option<Self> {
+        let display = error.to_string();
+        let debug = format!("{error:?}");
+
+        match (redact(&display, token), redact(&debug, token)) {
+            (Cow::Borrowed(_), Cow::Borrowed(_)) => None,
+            (message, _) => Some(Self {
+                message: message.into_owned(),
+            }),
+        }
+    }
+}
+
+fn redact<'a>(text: &'a str, token: Option<&ApiToken>) -> Cow<'a, str> {
+    token.map_or_else(|| redact_tokens(text), |token| token.redact(text))
+}
+
+fn redact_value(value: &mut Value, token: Option<&ApiToken>) {
+    match value {
+        Value::String(string) => {
+            if let Cow::Owned(redacted) = redact(string, token) {
+                *string = redacted;
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                redact_value(value, token);
+            }
+        }
+        Value::Object(object) => {
+            for value in object.values_mut() {
+                redact_value(value, token);
+            }
+        }
+        _ => {}
+    }
+}
+
+impl ConogramError {
+    /// Redact `token`, or anything that looks like a bot token if it's `None`, from the params and the error
+    pub(crate) fn redact(mut self, token: Option<&ApiToken>) -> Self {
+        redact_value(&mut self.params, token);
+        self.type_ = self.type_.redact(token);
+        self
+    }
+}
+
+impl ConogramErrorType {
+    pub(crate) fn redact(self, token: Option<&ApiToken>) -> Self {
+        match self {
+            Self::RequestError(mut err) => {
+                if let Some(url) = err.url_mut()
+                    && let Cow::Owned(redacted) = redact(url.as_str(), token)
+                {
+                    match reqwest::Url::parse(&redacted) {
+                        Ok(redacted) => *url = redacted,
+                        Err(_) => return Self::RequestError(err.without_url()),
+                    }
+                }
+                Self::RequestError(err)
+            }
+            Self::TransportError(err) => match RedactedError::new(&*err, token) {
+                Some(redacted) => Self::TransportError(Box::new(redacted)),
+                None => Self::TransportError(err),
+            },
+            Self::IO(err) => match RedactedError::new(&err, token) {
+                Some(redacted) => Self::IO(std::io::Error::new(err.kind(), redacted)),
+                None => Self::IO(err),
+            },
+            Self::ApiError(mut err) => {
+                if let Some(description) = &mut err.params_mut().description
+                    && let Cow::Owned(redacted) = redact(description, token)
+                {
+                    *description = redacted;
+                }
+                Self::ApiError(err)
+            }
+            other => other,
+        }
+    }
+}
@@ -11,11 +11,17 @@ use serde::{Serialize, de::DeserializeOwned};
 use crate::{
     api::Api,
     entities::misc::{chat_id::ChatId, input_file::GetFiles},
-    errors::{ConogramError, ConogramErrorType, TgApiError},
+    errors::ConogramError,
+    retry::{RetryPolicy, retry_request},
 };
 
 pub trait TargetChatId {
     fn get_target_chat_id(&self) -> Option<ChatId>;
+
+    /// Whether `allow_paid_broadcast` is set, such requests bypass the [RateLimiter](crate::rate_limiter::RateLimiter)
+    fn allows_paid_broadcast(&self) -> bool {
+        false
+    }
 }
 
 pub trait RequestT
@@ -39,8 +45,9 @@ where
     /// Execute the request
     fn send_ref(&self) -> impl Future<Output = Result<Self::ReturnType, ConogramError>> + Send {
         async {
-            self.get_api_ref()
-                .method_json(Self::get_name(), Some(self.get_params_ref()))
+            let api = self.get_api_ref();
+            api.wait_rate_limit::<Self>(self.get_params_ref()).await;
+            api.method_json(Self::get_name(), Some(self.get_params_ref()))
                 .await
         }
     }
@@ -59,75 +66,43 @@ where
         Self::ParamsType: GetFiles,
     {
         async {
-            self.get_api_ref()
-                .method_multipart_form(Self::get_name(), Some(self.get_params_ref()))
+            let api = self.get_api_ref();
+            api.wait_rate_limit::<Self>(self.get_params_ref()).await;
+            api.method_multipart_form(Self::get_name(), Some(self.get_params_ref()))
                 .await
         }
     }
 
     /// Execute the request, automatically handling flood wait and BadGateway, GatewayTimeout errors
+    ///
+    /// Errors are retried according to the [retry policy](Api::set_retry_policy) of the [Api].
+    /// Requests to migrated groups are retried against the new chat if [`Api::set_chat_migrations`] is enabled
     fn wrap(&self) -> impl Future<Output = Result<Self::ReturnType, ConogramError>> + Send
+    where
+        for<'a> &'a Self: IntoFuture<Output = Result<Self::ReturnType, ConogramError>>,
+        for<'a> <&'a Self as IntoFuture>::IntoFuture: Send,
+    {
+        async move { self.wrap_with(self.get_api_ref().retry_policy()).await }
+    }
+
+    /// The same as [RequestT::wrap()] but with a custom [RetryPolicy] instead of the [Api]'s one
+    fn wrap_with(
+        &self,
+        retry_policy: &dyn RetryPolicy,
+    ) -> impl Future<Output = Result<Self::ReturnType, ConogramError>> + Send
     where
         for<'a> &'a Self: IntoFuture<Output = Result<Self::ReturnType, ConogramError>>,
         for<'a> <&'a Self as IntoFuture>::IntoFuture: Send,
     {
         async move {
-            let mut result = self.await;
-
-            let mut wait_for = 1;
-
-            while !match &result {
-                Err(err) => {
-                    if let ConogramErrorType::ApiError(error) = &err.type_ {
-                        match error {
-                            TgApiError::RetryAfter(params) => {
-                                if let Some(params) = params.parameters.as_ref() {
-                                    let retry_after = params.retry_after.unwrap_or_default();
-                                    if retry_after > 0 {
-                                        let mut retry_after = retry_after as u64;
-                                        log::debug!(
-                                            "Got RetryAfter {retry_after}s in {}",
-                                            Self::get_name()
-                                        );
-
-                                        if retry_after > 600 {
-                                            log::warn!(
-                                                "Unusually high RetryAfter: {retry_after}s, clamping to 600s"
-                                            );
-                                            retry_after = 600;
-                                        }
-
-                                        self.get_api_ref()
-                                            .register_flood_wait_hit(self, retry_after);
-                                        tokio::time::sleep(Duration::from_secs(retry_after)).await;
-                                    } else {
-                                        log::warn!("RetryAfter is negative: {retry_after}");
-                                    }
-
-                                    result = self.await;
-                                    false
-                                } else {
-                                    true
-                                }
-                            }
-                            TgApiError::BadGateway(_) | TgApiError::GatewayTimeout(_) => {
-                                wait_for = std::cmp::min(wait_for * 2, 60);
-                                log::debug!("Got gateway error, retrying in {wait_for}s");
-                                tokio::time::sleep(Duration::from_secs(wait_for)).await;
-                                result = self.await;
-                                false
-                            }
-                            _ => true,
-                        }
-                    } else {
-                        true
-                    }
-                }
-
-                _ => true,
-            } {}
-
-            result
+            retry_request(
+                self.get_api_ref(),
+                retry_policy,
+                Self::get_name(),
+                self.get_params_ref().get_target_chat_id().as_ref(),
+                || self.into_future(),
+            )
+            .await
         }
     }
 
@@ -0,0 +1,347 @@
+//! Retrying of failed requests
+//!
+//! ```rust, ignore
+//! // Give up after 5 attempts or a minute of waiting, retry network errors too
+//! api.set_retry_policy(
+//!     DefaultRetryPolicy::new()
+//!         .max_attempts(5)
+//!         .max_total_wait(Duration::from_mins(1))
+//!         .retry_transport_errors(true)
+//!         .on_retry(|context, delay| log::info!("Retrying {} in {delay:?}: {}", context.method, context.error)),
+//! );
+//!
+//! // Or for a single request
+//! api.send_message(chat_id, "Hi").wrap_with(&DefaultRetryPolicy::never()).await?;
+//! ```
+
+use std::{fmt::Debug, future::Future, sync::Arc, time::Duration};
+
+use crate::{
+    api::Api,
+    entities::misc::chat_id::ChatId,
+    errors::{ConogramError, ConogramErrorType, TgApiError},
+};
+
+/// Failed attempt of a request, passed to the [RetryPolicy]
+#[derive(Debug)]
+pub struct RetryContext<'a> {
+    /// Bot API method name, e.g. `sendMessage`
+    pub method: &'a str,
+    pub chat_id: Option<&'a ChatId>,
+    pub error: &'a ConogramError,
+
+    /// Number of failed attempts so far, starting from 1
+    pub attempt: u32,
+
+    /// Time spent waiting between previous attempts
+    pub waited: Duration,
+}
+
+impl RetryContext<'_> {
+    /// `retry_after` of a flood wait error, `None` for other errors
+    #[must_use]
+    pub fn retry_after(&self) -> Option<i64> {
+        match &self.error.type_ {
+            ConogramErrorType::ApiError(TgApiError::RetryAfter(params)) => {
+                params.parameters.as_ref()?.retry_after
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Decides whether and when failed requests are sent again, see [`Api::set_retry_policy`] and [`RequestT::wrap_with`](crate::request::RequestT::wrap_with)
+pub trait RetryPolicy: Debug + Send + Sync {
+    /// Delay before the next attempt, `None` to give up and return the error
+    fn retry_delay(&self, context: &RetryContext<'_>) -> Option<Duration>;
+
+    /// Called before waiting `delay` and retrying
+    fn before_retry(&self, context: &RetryContext<'_>, delay: Duration) {
+        let _ = (context, delay);
+    }
+}
+
+pub type RetryHook = dyn Fn(&RetryContext<'_>, Duration) + Send + Sync;
+
+/// [RetryPolicy] used by default
+///
+/// Waits out flood waits, retries `BadGateway` and `GatewayTimeout` errors with exponential backoff,
+/// without limits on attempts and total wait time
+#[derive(Clone)]
+pub struct DefaultRetryPolicy {
+    max_attempts: Option<u32>,
+    max_total_wait: Option<Duration>,
+    max_retry_after: Duration,
+    min_backoff: Duration,
+    max_backoff: Duration,
+    jitter: f64,
+
+    retry_flood_wait: bool,
+    retry_server_errors: bool,
+    retry_transport_errors: bool,
+
+    on_retry: Option<Arc<RetryHook>>,
+}
+
+impl Default for DefaultRetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: None,
+            max_total_wait: None,
+            max_retry_after: Duration::from_mins(10),
+            min_backoff: Duration::from_secs(2),
+            max_backoff: Duration::from_mins(1),
+            jitter: 0.0,
+            retry_flood_wait: true,
+            retry_server_errors: true,
+            retry_transport_errors: false,
+            on_retry: None,
+        }
+    }
+}
+
+impl Debug for DefaultRetryPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DefaultRetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("max_total_wait", &self.max_total_wait)
+            .field("max_retry_after", &self.max_retry_after)
+            .field("min_backoff", &self.min_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("jitter", &self.jitter)
+            .field("retry_flood_wait", &self.retry_flood_wait)
+            .field("retry_server_errors", &self.retry_server_errors)
+            .field("retry_transport_errors", &self.retry_transport_errors)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DefaultRetryPolicy {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Policy which never retries
+    #[must_use]
+    pub fn never() -> Self {
+        Self::new().max_attempts(1)
+    }
+
+    /// Max number of attempts, including the first one
+    #[must_use]
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts.max(1));
+        self
+    }
+
+    /// Give up instead of waiting if the total wait time would exceed `max_total_wait`
+    #[must_use]
+    pub const fn max_total_wait(mut self, max_total_wait: Duration) -> Self {
+        self.max_total_wait = Some(max_total_wait);
+        self
+    }
+
+    /// Flood waits longer than this are shortened to it, 10 minutes by default
+    #[must_use]
+    pub const fn max_retry_after(mut self, max_retry_after: Duration) -> Self {
+        self.max_retry_after = max_retry_after;
+        self
+    }
+
+    /// Backoff for server and transport errors starts from `min` and doubles up to `max`, 2 seconds and 1 minute by default
+    #[must_use]
+    pub const fn backoff(mut self, min: Duration, max: Duration) -> Self {
+        self.min_backoff = min;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Add a random delay of up to `jitter` (0.0-1.0) of each delay, so many clients don't retry at once. 0.0 by default
+    #[must_use]
+    pub const fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Retry `RetryAfter` errors after the returned time, `true` by default
+    #[must_use]
+    pub const fn retry_flood_wait(mut self, retry: bool) -> Self {
+        self.retry_flood_wait = retry;
+        self
+    }
+
+    /// Retry `BadGateway` and `GatewayTimeout` errors, `true` by default
+    #[must_use]
+    pub const fn retry_server_errors(mut self, retry: bool) -> Self {
+        self.retry_server_errors = retry;
+        self
+    }
+
+    /// Retry network errors and errors of custom [Transport](crate::transport::Transport)s, `false` by default
+    ///
+    /// Note: the request may have reached the server, so e.g. a message may be sent twice
+    #[must_use]
+    pub const fn retry_transport_errors(mut self, retry: bool) -> Self {
+        self.retry_transport_errors = retry;
+        self
+    }
+
+    /// Called before each retry, e.g. for logging or metrics
+    #[must_use]
+    pub fn on_retry(
+        mut self,
+        on_retry: impl Fn(&RetryContext<'_>, Duration) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_retry = Some(Arc::new(on_retry));
+        self
+    }
+
+    fn backoff_delay(&self, attempt: u32) -> Duration {
+        self.min_backoff
+            .saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff)
+    }
+
+    fn with_jitter(&self, delay: Duration) -> Duration {
+        if self.jitter <= 0.0 {
+            return delay;
+        }
+
+        let random = (uuid::Uuid::new_v4().as_u128() % 1_000_000) as f64 / 1_000_000.0;
+        delay.mul_f64(self.jitter.mul_add(random, 1.0))
+    }
+}
+
+impl RetryPolicy for DefaultRetryPolicy {
+    fn retry_delay(&self, context: &RetryContext<'_>) -> Option<Duration> {
+        if self
+            .max_attempts
+            .is_some_and(|max_attempts| context.attempt >= max_attempts)
+        {
+            return None;
+        }
+
+        let delay = match &context.error.type_ {
+            ConogramErrorType::ApiError(TgApiError::RetryAfter(_)) if self.retry_flood_wait => {
+                let retry_after = context.retry_after()?;
+                if retry_after < 0 {
+                    log::warn!("RetryAfter is negative: {retry_after}");
+                }
+
+                let retry_after = Duration::from_secs(retry_after.max(0) as u64);
+                if retry_after > self.max_retry_after {
+                    log::warn!(
+                        "Unusually high RetryAfter: {retry_after:?}, clamping to {:?}",
+                        self.max_retry_after
+                    );
+                }
+                retry_after.min(self.max_retry_after)
+            }
+            ConogramErrorType::ApiError(
+                TgApiError::BadGateway(_) | TgApiError::GatewayTimeout(_),
+            ) if self.retry_server_errors => self.backoff_delay(context.attempt),
+            ConogramErrorType::RequestError(_) | ConogramErrorType::TransportError(_)
+                if self.retry_transport_errors =>
+            {
+                self.backoff_delay(context.attempt)
+            }
+            _ => return None,
+        };
+        let delay = self.with_jitter(delay);
+
+        if self
+            .max_total_wait
+            .is_some_and(|max_total_wait| context.waited + delay > max_total_wait)
+        {
+            return None;
+        }
+
+        Some(delay)
+    }
+
+    fn before_retry(&self, context: &RetryContext<'_>, delay: Duration) {
+        if let Some(on_retry) = &self.on_retry {
+            on_retry(context, delay);
+        }
+    }
+}
+
+/// Retry loop shared by [`RequestT::wrap`](crate::request::RequestT::wrap), [`Api::request_ref`] and the scheduler
+///
+/// Flood waits are registered for [`Api::get_flood_wait_duration`], requests to migrated groups are retried once
+/// if [ChatMigrations](crate::chat_migrations::ChatMigrations) are enabled, then `retry_policy` decides
+pub(crate) async fn retry_request<ReturnType, Fut>(
+    api: &Api,
+    retry_policy: &dyn RetryPolicy,
+    method: &str,
+    chat_id: Option<&ChatId>,
+    mut send: impl FnMut() -> Fut,
+) -> Result<ReturnType, ConogramError>
+where
+    Fut: Future<Output = Result<ReturnType, ConogramError>>,
+{
+    let mut attempt = 0;
+    let mut waited = Duration::ZERO;
+    let mut migrated = false;
+
+    loop {
+        let error = match send().await {
+            Ok(result) => return Ok(result),
+            Err(error) => error,
+        };
+        attempt += 1;
+
+        let context = RetryContext {
+            method,
+            chat_id,
+            error: &error,
+            attempt,
+            waited,
+        };
+
+        if let Some(retry_after) = context.retry_after()
+            && retry_after > 0
+        {
+            api.register_flood_wait_hit(method, chat_id, retry_after as u64);
+        }
+
+        if !migrated && migrate_chat(api, &context) {
+            // Params are rewritten to the new chat id when sent again
+            migrated = true;
+            continue;
+        }
+
+        let Some(delay) = retry_policy.retry_delay(&context) else {
+            return Err(error);
+        };
+
+        log::debug!("Retrying {method} in {delay:?} after: {error}");
+        if let Some(metrics_sink) = api.metrics_sink() {
+            metrics_sink.record_retry(method, &error.type_);
+        }
+        retry_policy.before_retry(&context, delay);
+        tokio::time::sleep(delay).await;
+        waited += delay;
+    }
+}
+
+/// Register the migration if the request failed because of it, returns `true` if the request should be retried
+fn migrate_chat(api: &Api, context: &RetryContext<'_>) -> bool {
+    let ConogramErrorType::ApiError(TgApiError::Generic(params)) = &context.error.type_ else {
+        return false;
+    };
+
+    if let Some(to_chat_id) = params
+        .parameters
+        .as_ref()
+        .and_then(|p| p.migrate_to_chat_id)
+        && let Some(chat_migrations) = api.chat_migrations()
+        && let Some(ChatId::Id(from_chat_id)) = context.chat_id
+    {
+        chat_migrations.insert(*from_chat_id, to_chat_id);
+        return chat_migrations.retries();
+    }
+
+    false
+}
@@ -0,0 +1,267 @@
+//! Delayed and repeating API calls, which survive restarts
+//!
+//! ```rust, ignore
+//! let scheduler = Scheduler::new(JsonFileJobStorage::open("jobs.json").await?);
+//!
+//! // Send in 2 hours
+//! let request = api.send_message(chat_id, "Reminder");
+//! scheduler.schedule(Job::new(&request, SystemTime::now() + Duration::from_secs(7200))?).await?;
+//!
+//! // Post every day, starting from the next 09:00 UTC
+//! let request = api.send_message(channel_id, "Good morning");
+//! scheduler.schedule(Job::new(&request, next_nine_am)?.every(Duration::from_secs(86400))).await?;
+//!
+//! tokio::spawn(async move { scheduler.run(&api, CancellationToken::new()).await });
+//! ```
+
+mod storage;
+
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+use tokio::sync::Notify;
+
+pub use self::storage::{JobStorage, JsonFileJobStorage, MemoryJobStorage};
+use crate::{
+    api::Api, entities::misc::chat_id::ChatId, errors::ConogramError, polling::CancellationToken,
+    request::RequestT, retry::retry_request,
+};
+
+/// Due jobs are checked at least this often, so changes made to the storage directly are noticed
+const MAX_SLEEP: Duration = Duration::from_mins(1);
+
+#[derive(Debug, Error)]
+pub enum SchedulerError {
+    /// Job (de)serialization errors
+    #[error("{0}")]
+    SerdeError(#[from] serde_json::Error),
+
+    /// Storage IO errors
+    #[error("{0}")]
+    IO(#[from] std::io::Error),
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// API call scheduled for a specific time
+///
+/// Note: params are stored as JSON, so files must be passed by `file_id` or URL, uploads are not supported
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Job {
+    pub id: String,
+
+    /// Bot API method name, e.g. `sendMessage`
+    pub method: String,
+    pub params: Value,
+
+    /// Unix time in milliseconds when the job is run
+    pub run_at: u64,
+
+    /// Repeat interval in milliseconds, the job is removed after running if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat_every: Option<u64>,
+}
+
+impl Job {
+    /// Run `request` at `run_at`
+    pub fn new<Request: RequestT>(
+        request: &Request,
+        run_at: SystemTime,
+    ) -> Result<Self, serde_json::Error> {
+        Self::raw(Request::get_name(), request.get_params_ref(), run_at)
+    }
+
+    /// Call `method` with `params` at `run_at`
+    pub fn raw(
+        method: impl Into<String>,
+        params: impl Serialize,
+        run_at: SystemTime,
+    ) -> Result<Self, serde_json::Error> {
+        Ok(Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            method: method.into(),
+            params: serde_json::to_value(params)?,
+            run_at: unix_millis(run_at),
+            repeat_every: None,
+        })
+    }
+
+    /// Run the job repeatedly, `interval` after each scheduled time
+    #[must_use]
+    pub fn every(mut self, interval: Duration) -> Self {
+        self.repeat_every = Some((interval.as_millis() as u64).max(1));
+        self
+    }
+
+    #[must_use]
+    pub fn run_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.run_at)
+    }
+
+    /// Next run time for repeating jobs, runs missed while the bot was down are skipped
+    fn next_run_at(&self, now: u64) -> Option<u64> {
+        let interval = self.repeat_every?;
+        let missed = now.saturating_sub(self.run_at) / interval;
+        Some(self.run_at + (missed + 1) * interval)
+    }
+
+    async fn execute(&self, api: &Api) -> Result<Value, ConogramError> {
+        let chat_id = ChatId::deserialize(&self.params["chat_id"]).ok();
+        let paid_broadcast = self.params["allow_paid_broadcast"]
+            .as_bool()
+            .unwrap_or_default();
+
+        retry_request(
+            api,
+            api.retry_policy(),
+            &self.method,
+            chat_id.as_ref(),
+            || async {
+                api.wait_method_rate_limit(&self.method, chat_id.as_ref(), paid_broadcast)
+                    .await;
+                api.method_json::<Value, Value>(&self.method, Some(&self.params))
+                    .await
+            },
+        )
+        .await
+    }
+}
+
+struct SchedulerInner<St> {
+    storage: St,
+    changed: Notify,
+}
+
+/// Shared job scheduler, cheap to clone
+///
+/// Notes:
+/// * Jobs are run with [`RequestT::wrap`] semantics, i.e. flood waits and gateway errors are retried
+/// * Failed jobs are logged and not retried, repeating ones are run again at the next time
+/// * A job is removed (or rescheduled) after it's run, so a job which was running during a crash is run again after restart
+pub struct Scheduler<St = MemoryJobStorage> {
+    inner: Arc<SchedulerInner<St>>,
+}
+
+impl<St> Clone for Scheduler<St> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<St> std::fmt::Debug for Scheduler<St> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Scheduler").finish_non_exhaustive()
+    }
+}
+
+impl<St: JobStorage> Scheduler<St> {
+    pub fn new(storage: St) -> Self {
+        Self {
+            inner: Arc::new(SchedulerInner {
+                storage,
+                changed: Notify::new(),
+            }),
+        }
+    }
+
+    /// Add the job, returns its id
+    pub async fn schedule(&self, job: Job) -> Result<String, SchedulerError> {
+        let id = job.id.clone();
+        self.inner.storage.save(job).await?;
+        self.inner.changed.notify_one();
+        Ok(id)
+    }
+
+    /// Returns `false` if there was no such job
+    pub async fn cancel(&self, id: &str) -> Result<bool, SchedulerError> {
+        let removed = self.inner.storage.remove(id).await?;
+        self.inner.changed.notify_one();
+        Ok(removed)
+    }
+
+    /// Scheduled jobs, sorted by run time
+    pub async fn list(&self) -> Result<Vec<Job>, SchedulerError> {
+        let mut jobs = self.inner.storage.list().await?;
+        jobs.sort_by_key(|job| job.run_at);
+        Ok(jobs)
+    }
+
+    /// Run due jobs until cancelled. Only one runner should be active for the storage
+    pub async fn run(
+        &self,
+        api: &Api,
+        cancellation_token: CancellationToken,
+    ) -> Result<(), SchedulerError> {
+        while !cancellation_token.is_cancelled() {
+            let now = unix_millis(SystemTime::now());
+            let jobs = self.list().await?;
+
+            let (due, pending): (Vec<_>, Vec<_>) =
+                jobs.into_iter().partition(|job| job.run_at <= now);
+            if !due.is_empty() {
+                for job in due {
+                    if cancellation_token.is_cancelled() {
+                        return Ok(());
+                    }
+                    self.run_job(api, job).await?;
+                }
+                // Rescheduled jobs are picked up on the next iteration
+                continue;
+            }
+
+            let sleep_for = pending.first().map_or(MAX_SLEEP, |job| {
+                Duration::from_millis(job.run_at.saturating_sub(unix_millis(SystemTime::now())))
+                    .min(MAX_SLEEP)
+            });
+
+            tokio::select! {
+                () = tokio::time::sleep(sleep_for) => {}
+                () = self.inner.changed.notified() => {}
+                () = cancellation_token.cancelled() => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn contains(&self, id: &str) -> Result<bool, SchedulerError> {
+        Ok(self
+            .inner
+            .storage
+            .list()
+            .await?
+            .iter()
+            .any(|job| job.id == id))
+    }
+
+    async fn run_job(&self, api: &Api, job: Job) -> Result<(), SchedulerError> {
+        // The job could have been cancelled while previous ones were running
+        if !self.contains(&job.id).await? {
+            return Ok(());
+        }
+
+        if let Err(err) = job.execute(api).await {
+            log::warn!("Scheduled job {} ({}) failed: {err}", job.id, job.method);
+        }
+
+        match job.next_run_at(unix_millis(SystemTime::now())) {
+            // Not rescheduled if cancelled while running
+            Some(run_at) if self.contains(&job.id).await? => {
+                self.inner.storage.save(Job { run_at, ..job }).await
+            }
+            Some(_) => Ok(()),
+            None => self.inner.storage.remove(&job.id).await.map(|_| ()),
+        }
+    }
+}
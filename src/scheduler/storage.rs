@@ -0,0 +1,113 @@
+use std::{collections::HashMap, future::Future, path::PathBuf};
+
+use dashmap::DashMap;
+use tokio::sync::Mutex;
+
+use super::{Job, SchedulerError};
+
+/// Storage of scheduled [Job]s
+pub trait JobStorage: Send + Sync + 'static {
+    fn list(&self) -> impl Future<Output = Result<Vec<Job>, SchedulerError>> + Send;
+
+    /// Insert the job or replace the one with the same id
+    fn save(&self, job: Job) -> impl Future<Output = Result<(), SchedulerError>> + Send;
+
+    /// Returns `false` if there was no such job
+    fn remove(&self, id: &str) -> impl Future<Output = Result<bool, SchedulerError>> + Send;
+}
+
+/// Keeps jobs in memory, they are lost on restart
+#[derive(Debug, Default)]
+pub struct MemoryJobStorage {
+    jobs: DashMap<String, Job>,
+}
+
+impl MemoryJobStorage {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl JobStorage for MemoryJobStorage {
+    async fn list(&self) -> Result<Vec<Job>, SchedulerError> {
+        Ok(self.jobs.iter().map(|job| job.clone()).collect())
+    }
+
+    async fn save(&self, job: Job) -> Result<(), SchedulerError> {
+        self.jobs.insert(job.id.clone(), job);
+        Ok(())
+    }
+
+    async fn remove(&self, id: &str) -> Result<bool, SchedulerError> {
+        Ok(self.jobs.remove(id).is_some())
+    }
+}
+
+/// Keeps all jobs in a single JSON file, which is rewritten on every change
+///
+/// Suitable for a moderate number of jobs, all of them are also kept in memory
+#[derive(Debug)]
+pub struct JsonFileJobStorage {
+    path: PathBuf,
+    jobs: Mutex<HashMap<String, Job>>,
+}
+
+impl JsonFileJobStorage {
+    /// Load jobs from `path`, the file is created on the first change if it doesn't exist
+    pub async fn open(path: impl Into<PathBuf>) -> Result<Self, SchedulerError> {
+        let path = path.into();
+
+        let jobs = match tokio::fs::read(&path).await {
+            Ok(content) => serde_json::from_slice::<Vec<Job>>(&content)?
+                .into_iter()
+                .map(|job| (job.id.clone(), job))
+                .collect(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Self {
+            path,
+            jobs: Mutex::new(jobs),
+        })
+    }
+
+    async fn save_file(&self, jobs: &HashMap<String, Job>) -> Result<(), SchedulerError> {
+        let mut jobs: Vec<_> = jobs.values().collect();
+        jobs.sort_by_key(|job| job.run_at);
+
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+
+        tokio::fs::write(&tmp_path, serde_json::to_vec(&jobs)?).await?;
+        tokio::fs::rename(tmp_path, &self.path).await?;
+        Ok(())
+    }
+}
+
+impl JobStorage for JsonFileJobStorage {
+    async fn list(&self) -> Result<Vec<Job>, SchedulerError> {
+        Ok(self.jobs.lock().await.values().cloned().collect())
+    }
+
+    async fn save(&self, job: Job) -> Result<(), SchedulerError> {
+        let mut jobs = self.jobs.lock().await;
+        jobs.insert(job.id.clone(), job);
+        // Lock is held while writing, so older content can't overwrite newer one
+        let result = self.save_file(&jobs).await;
+        drop(jobs);
+        result
+    }
+
+    async fn remove(&self, id: &str) -> Result<bool, SchedulerError> {
+        let mut jobs = self.jobs.lock().await;
+        let result = if jobs.remove(id).is_some() {
+            self.save_file(&jobs).await.map(|()| true)
+        } else {
+            Ok(false)
+        };
+        drop(jobs);
+        result
+    }
+}
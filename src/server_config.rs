@@ -1,3 +1,5 @@
+const REMOTE_API_URL: &str = "https://api.telegram.org";
+
 #[derive(Debug, Clone)]
 /// See [Using bot in test environment](https://core.telegram.org/bots/webapps#using-bots-in-the-test-environment)
 /// See [Using local api server](https://core.telegram.org/bots/api#using-a-local-bot-api-server)
@@ -15,7 +17,7 @@ impl ApiServerConfig {
     #[must_use]
     pub fn remote(use_test_env: bool) -> Self {
         Self {
-            url: "https://api.telegram.org".to_string(),
+            url: REMOTE_API_URL.to_string(),
             use_test_env,
         }
     }
@@ -27,6 +29,13 @@ impl ApiServerConfig {
             use_test_env,
         }
     }
+
+    /// Whether the config points to a [local server](https://core.telegram.org/bots/api#using-a-local-bot-api-server),
+    /// which has no file size limits
+    #[must_use]
+    pub fn is_local(&self) -> bool {
+        self.url.trim_end_matches('/') != REMOTE_API_URL
+    }
 }
 
 impl Default for ApiServerConfig {
@@ -0,0 +1,136 @@
+use std::{fmt::Debug, future::Future, pin::Pin};
+
+use reqwest::multipart::Form;
+use serde_json::Value;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::{errors::ConogramErrorType, redact::redact_tokens};
+
+pub type TransportFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Vec<u8>, ConogramErrorType>> + Send + 'a>>;
+
+pub type DownloadFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<u64, ConogramErrorType>> + Send + 'a>>;
+
+/// Bot API call, passed to the [Transport]
+///
+/// The token is [redacted](crate::redact) from the URL in Debug output
+pub struct TransportRequest {
+    /// Bot API method name, e.g. `sendMessage`
+    pub method: String,
+
+    /// Full method URL, contains the bot token
+    pub url: String,
+
+    /// Request params with default params applied, `None` for methods called without params
+    pub params: Option<Value>,
+
+    /// Set for requests which upload files, contains `params` along with the files
+    pub multipart: Option<Form>,
+}
+
+impl Debug for TransportRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TransportRequest")
+            .field("method", &self.method)
+            .field("url", &redact_tokens(&self.url))
+            .field("params", &self.params)
+            .field("multipart", &self.multipart)
+            .finish()
+    }
+}
+
+/// Sends Bot API requests over the network, or anywhere else
+///
+/// Notes:
+/// * Must return the response body regardless of the HTTP status, as Bot API errors are passed in the body
+/// * Custom transports should return errors as [`ConogramErrorType::TransportError`]
+/// * Errors which mention the bot token are [redacted](crate::redact), no need to strip URLs from them
+pub trait Transport: Debug + Send + Sync + 'static {
+    /// POST the request, as JSON or as `multipart/form-data` if [`TransportRequest::multipart`] is set
+    fn send(&self, request: TransportRequest) -> TransportFuture<'_>;
+
+    /// GET the file at `url` and write it into `writer`, returns the number of written bytes
+    ///
+    /// Must fail with [`ConogramErrorType::FileTooBig`] as soon as more than `max_size` bytes are received.
+    /// Not supported by default
+    fn download<'a>(
+        &'a self,
+        url: String,
+        max_size: Option<u64>,
+        writer: &'a mut (dyn AsyncWrite + Unpin + Send),
+    ) -> DownloadFuture<'a> {
+        let _ = (url, max_size, writer);
+        Box::pin(async {
+            Err(ConogramErrorType::TransportError(
+                "File downloads are not supported by this transport".into(),
+            ))
+        })
+    }
+}
+
+/// Default [Transport], backed by a [reqwest::Client]
+#[derive(Debug, Clone, Default)]
+pub struct ReqwestTransport {
+    client: reqwest::Client,
+}
+
+impl ReqwestTransport {
+    /// Use a preconfigured client, e.g. with a proxy or timeouts
+    #[must_use]
+    pub const fn new(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+}
+
+impl From<reqwest::Client> for ReqwestTransport {
+    fn from(client: reqwest::Client) -> Self {
+        Self::new(client)
+    }
+}
+
+impl Transport for ReqwestTransport {
+    fn send(&self, request: TransportRequest) -> TransportFuture<'_> {
+        Box::pin(async move {
+            let mut builder = self.client.post(request.url);
+            if let Some(form) = request.multipart {
+                builder = builder.multipart(form);
+            } else if let Some(params) = &request.params {
+                builder = builder.json(params);
+            }
+
+            let response = builder.send().await?;
+            Ok(response.bytes().await?.to_vec())
+        })
+    }
+
+    fn download<'a>(
+        &'a self,
+        url: String,
+        max_size: Option<u64>,
+        writer: &'a mut (dyn AsyncWrite + Unpin + Send),
+    ) -> DownloadFuture<'a> {
+        Box::pin(async move {
+            let mut response = self
+                .client
+                .get(url)
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status)?;
+
+            let mut size = 0;
+            while let Some(chunk) = response.chunk().await? {
+                size += chunk.len() as u64;
+                if let Some(max_size) = max_size
+                    && size > max_size
+                {
+                    return Err(ConogramErrorType::FileTooBig { size, max_size });
+                }
+                writer.write_all(&chunk).await?;
+            }
+            writer.flush().await?;
+
+            Ok(size)
+        })
+    }
+}
@@ -0,0 +1,345 @@
+use std::{
+    convert::Infallible,
+    fmt::Debug,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::Stream;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::{
+    Method, Request, Response, StatusCode,
+    body::{Bytes, Incoming},
+    server::conn::http1,
+    service::service_fn,
+};
+use hyper_util::rt::TokioIo;
+use tokio::{net::TcpListener, sync::mpsc, task::JoinHandle};
+
+use crate::{
+    api::Api,
+    entities::update::Update,
+    errors::{ConogramError, ConogramErrorType},
+    request::RequestT,
+};
+
+/// Header Telegram uses to pass `secret_token` from [setWebhook](https://core.telegram.org/bots/api/#setwebhook)
+pub const SECRET_TOKEN_HEADER: &str = "X-Telegram-Bot-Api-Secret-Token";
+
+/// Updates are tiny, anything bigger is not coming from Telegram
+const MAX_BODY_SIZE: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    /// Local address to listen on, TLS is expected to be terminated by a reverse proxy
+    pub listen_addr: SocketAddr,
+
+    /// Path the updates are POSTed to, requests to other paths are answered with 404
+    ///
+    /// In a [BotPool](crate::bot_pool::BotPool) updates of each bot are POSTed to `{path}/{bot_id}`
+    pub path: String,
+
+    /// Expected value of the [SECRET_TOKEN_HEADER], requests without it are answered with 401
+    pub secret_token: Option<String>,
+
+    /// How many received updates can wait to be consumed before the server stops answering Telegram
+    pub max_pending_updates: usize,
+}
+
+impl WebhookConfig {
+    pub fn new(listen_addr: impl Into<SocketAddr>) -> Self {
+        Self {
+            listen_addr: listen_addr.into(),
+            path: "/".into(),
+            secret_token: None,
+            max_pending_updates: 100,
+        }
+    }
+
+    #[must_use]
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        let path = path.into();
+        self.path = if path.starts_with('/') {
+            path
+        } else {
+            format!("/{path}")
+        };
+        self
+    }
+
+    /// 1-256 characters, only `A-Z`, `a-z`, `0-9`, `_` and `-` are allowed
+    #[must_use]
+    pub fn secret_token(mut self, secret_token: impl Into<String>) -> Self {
+        self.secret_token = Some(secret_token.into());
+        self
+    }
+
+    #[must_use]
+    pub fn max_pending_updates(mut self, max_pending_updates: usize) -> Self {
+        self.max_pending_updates = max_pending_updates.max(1);
+        self
+    }
+}
+
+struct WebhookContext {
+    path: String,
+    secret_token: Option<String>,
+
+    /// Updates are POSTed to `{path}/{bot_id}`, see [`BotPool::listen_webhook`](crate::bot_pool::BotPool::listen_webhook)
+    per_bot: bool,
+    sender: mpsc::Sender<(Option<i64>, Update)>,
+}
+
+impl WebhookContext {
+    /// Bot id from a `{path}/{bot_id}` request path
+    fn path_bot_id(&self, path: &str) -> Option<i64> {
+        path.strip_prefix(self.path.trim_end_matches('/'))?
+            .strip_prefix('/')?
+            .parse()
+            .ok()
+    }
+}
+
+/// Webhook listener, which accepts updates from Telegram
+///
+/// Updates received by the server are not passed to [`Api::preprocess_updates`] until they are consumed
+/// via [`WebhookServer::recv`] or [`WebhookServer::into_stream`]
+pub struct WebhookServer {
+    local_addr: SocketAddr,
+    receiver: mpsc::Receiver<(Option<i64>, Update)>,
+    task: JoinHandle<()>,
+}
+
+impl Debug for WebhookServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebhookServer")
+            .field("local_addr", &self.local_addr)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WebhookServer {
+    /// Start listening on [`WebhookConfig::listen_addr`]
+    ///
+    /// Note: this won't call [setWebhook](https://core.telegram.org/bots/api/#setwebhook), see [`Api::listen_webhook`]
+    pub async fn bind(config: WebhookConfig) -> std::io::Result<Self> {
+        Self::bind_inner(config, false).await
+    }
+
+    /// Start listening for updates of many bots, each one POSTed to `{path}/{bot_id}`
+    pub(crate) async fn bind_per_bot(config: WebhookConfig) -> std::io::Result<Self> {
+        Self::bind_inner(config, true).await
+    }
+
+    async fn bind_inner(config: WebhookConfig, per_bot: bool) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(config.listen_addr).await?;
+        let local_addr = listener.local_addr()?;
+
+        let (sender, receiver) = mpsc::channel(config.max_pending_updates);
+        let context = Arc::new(WebhookContext {
+            path: config.path,
+            secret_token: config.secret_token,
+            per_bot,
+            sender,
+        });
+
+        let task = tokio::spawn(Self::accept_loop(listener, context));
+        log::debug!("Webhook server is listening on {local_addr}");
+
+        Ok(Self {
+            local_addr,
+            receiver,
+            task,
+        })
+    }
+
+    /// Address the server is actually bound to, useful when listening on port 0
+    #[must_use]
+    pub const fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Wait for the next update. Returns `None` if the server has stopped
+    pub async fn recv(&mut self, api: &Api) -> Option<Update> {
+        let (_, update) = self.receiver.recv().await?;
+        api.preprocess_updates(std::slice::from_ref(&update));
+        Some(update)
+    }
+
+    /// Next update along with the id of the bot it was sent to, updates are not preprocessed
+    pub(crate) async fn recv_per_bot(&mut self) -> Option<(i64, Update)> {
+        loop {
+            if let (Some(bot_id), update) = self.receiver.recv().await? {
+                return Some((bot_id, update));
+            }
+        }
+    }
+
+    /// Turn the server into a [Stream] of updates, which are preprocessed by `api` the same way [`Api::poll_once`] does it
+    #[must_use]
+    pub const fn into_stream(self, api: &Api) -> WebhookUpdates<'_> {
+        WebhookUpdates { api, server: self }
+    }
+
+    async fn accept_loop(listener: TcpListener, context: Arc<WebhookContext>) {
+        loop {
+            let (stream, remote_addr) = match listener.accept().await {
+                Ok(v) => v,
+                Err(err) => {
+                    log::warn!("Webhook server failed to accept a connection: {err}");
+                    continue;
+                }
+            };
+
+            let context = context.clone();
+            tokio::spawn(async move {
+                let service = service_fn(|request| Self::handle(request, context.clone()));
+                if let Err(err) = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    log::debug!("Webhook connection with {remote_addr} failed: {err}");
+                }
+            });
+        }
+    }
+
+    async fn handle(
+        request: Request<Incoming>,
+        context: Arc<WebhookContext>,
+    ) -> Result<Response<Full<Bytes>>, Infallible> {
+        let path = request.uri().path();
+        let bot_id = if context.per_bot {
+            match context.path_bot_id(path) {
+                Some(bot_id) => Some(bot_id),
+                None => return Ok(Self::response(StatusCode::NOT_FOUND)),
+            }
+        } else if path == context.path {
+            None
+        } else {
+            return Ok(Self::response(StatusCode::NOT_FOUND));
+        };
+        if request.method() != Method::POST {
+            return Ok(Self::response(StatusCode::METHOD_NOT_ALLOWED));
+        }
+
+        if let Some(secret_token) = &context.secret_token {
+            let received = request
+                .headers()
+                .get(SECRET_TOKEN_HEADER)
+                .map(hyper::http::HeaderValue::as_bytes);
+            if received != Some(secret_token.as_bytes()) {
+                log::warn!("Webhook request with missing or wrong secret token was rejected");
+                return Ok(Self::response(StatusCode::UNAUTHORIZED));
+            }
+        }
+
+        let body = match Limited::new(request.into_body(), MAX_BODY_SIZE)
+            .collect()
+            .await
+        {
+            Ok(body) => body.to_bytes(),
+            Err(err) => {
+                log::warn!("Failed to read webhook request body: {err}");
+                return Ok(Self::response(StatusCode::BAD_REQUEST));
+            }
+        };
+
+        let update = match serde_json::from_slice::<Update>(&body) {
+            Ok(update) => update,
+            Err(err) => {
+                log::warn!("Failed to parse webhook update: {err}");
+                return Ok(Self::response(StatusCode::BAD_REQUEST));
+            }
+        };
+
+        // Answering only after the update is queued makes Telegram hold off the next ones when we're overloaded
+        if Box::pin(context.sender.send((bot_id, update)))
+            .await
+            .is_err()
+        {
+            return Ok(Self::response(StatusCode::SERVICE_UNAVAILABLE));
+        }
+
+        Ok(Self::response(StatusCode::OK))
+    }
+
+    fn response(status: StatusCode) -> Response<Full<Bytes>> {
+        let mut response = Response::new(Full::default());
+        *response.status_mut() = status;
+        response
+    }
+}
+
+impl Drop for WebhookServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// [Stream] of updates received by the [WebhookServer]
+#[derive(Debug)]
+pub struct WebhookUpdates<'a> {
+    api: &'a Api,
+    server: WebhookServer,
+}
+
+impl WebhookUpdates<'_> {
+    #[must_use]
+    pub const fn local_addr(&self) -> SocketAddr {
+        self.server.local_addr
+    }
+}
+
+impl Stream for WebhookUpdates<'_> {
+    type Item = Update;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let update =
+            std::task::ready!(self.server.receiver.poll_recv(cx)).map(|(_, update)| update);
+        if let Some(update) = &update {
+            self.api.preprocess_updates(std::slice::from_ref(update));
+        }
+        Poll::Ready(update)
+    }
+}
+
+impl Api {
+    /// Start the [WebhookServer] and register it with [setWebhook](https://core.telegram.org/bots/api/#setwebhook)
+    ///
+    /// Notes:
+    /// * `url` must be the public HTTPS URL which is proxied to [`WebhookConfig::listen_addr`] and [`WebhookConfig::path`]
+    /// * [`WebhookConfig::secret_token`] and [allowed updates](Api::set_allowed_updates) are passed to setWebhook
+    /// * Use [`Api::delete_webhook`] to go back to polling
+    pub async fn listen_webhook(
+        &self,
+        url: impl Into<String>,
+        config: WebhookConfig,
+    ) -> Result<WebhookServer, ConogramError> {
+        let url = url.into();
+        let secret_token = config.secret_token.clone();
+
+        let server = match WebhookServer::bind(config).await {
+            Ok(server) => server,
+            Err(err) => {
+                return Err(ConogramError::new(
+                    "setWebhook",
+                    &url,
+                    ConogramErrorType::IO(err),
+                ));
+            }
+        };
+
+        let mut request = self
+            .set_webhook(url)
+            .allowed_updates(self.allowed_updates.clone());
+        if let Some(secret_token) = secret_token {
+            request = request.secret_token(secret_token);
+        }
+        request.wrap().await?;
+
+        Ok(server)
+    }
+}